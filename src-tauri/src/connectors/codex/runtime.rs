@@ -15,17 +15,30 @@ use tauri::{AppHandle, Emitter};
 use tokio::{
   io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter},
   process::{Child, ChildStdin, Command},
-  sync::{mpsc, oneshot, Mutex, RwLock},
+  sync::{mpsc, oneshot, watch, Mutex, RwLock},
 };
 
 use super::types::{
+  ChatKey,
+  CodexApprovalDecision,
+  CodexApprovalRequest,
+  CodexChatSettings,
   CodexDoctor,
+  CodexServerRequest,
   CodexStatus,
   CodexThreadListResponse,
   CodexThreadReadResponse,
   CodexTranscriptItem,
+  CodexUserInputOption,
+  CodexUserInputQuestion,
+  CodexUserInputRequest,
+};
+use crate::core::{
+  config_store::{ApprovalPolicy, ChatLimits, SandboxMode, WorkspaceConfig},
+  fences::FenceMap,
+  logbus,
+  paths,
 };
-use crate::core::{logbus, paths};
 use std::collections::VecDeque;
 
 #[derive(Clone)]
@@ -35,7 +48,21 @@ pub struct CodexRuntime {
 
 pub struct CodexStream {
   pub updates_rx: mpsc::UnboundedReceiver<String>,
-  pub done_rx: oneshot::Receiver<Result<String, String>>,
+  pub done_rx: oneshot::Receiver<Result<CodexTurnResult, String>>,
+  // Approvals and questions Codex asks while the turn runs; answer via `resolve_approval` /
+  // `answer_user_input`.
+  pub requests_rx: mpsc::UnboundedReceiver<CodexServerRequest>,
+  // Full answer text so far, updated on every delta (for clients that redraw instead of append).
+  pub progress_rx: watch::Receiver<String>,
+  // Where the turn runs, so clients can link their messages back to it.
+  pub thread_id: String,
+  pub turn_id: String,
+}
+
+pub struct CodexTurnResult {
+  pub text: String,
+  // The turn was stopped via `interrupt_turn`; `text` holds the partial answer.
+  pub interrupted: bool,
 }
 
 struct Inner {
@@ -48,11 +75,25 @@ struct Inner {
   install_lock: Mutex<()>,
   next_id: AtomicU64,
   pending: Mutex<HashMap<u64, oneshot::Sender<Result<Value, String>>>>,
+  // ChatGPT sign-ins someone waits on, by loginId; resolved by account/login/completed.
+  login_waiters: Mutex<HashMap<String, oneshot::Sender<Result<(), String>>>>,
   pending_turns: Mutex<HashMap<String, PendingTurn>>,
-  chat_threads: Mutex<HashMap<i64, String>>,
+  // Server requests waiting for a user decision, keyed by local request id.
+  server_requests: Mutex<HashMap<u64, PendingServerRequest>>,
+  next_request_id: AtomicU64,
+  approval_timeout_sec: RwLock<u64>,
+  chat_threads: Mutex<HashMap<ChatKey, String>>,
   resumed_threads: Mutex<HashSet<String>>,
-  busy_chats: Mutex<HashSet<i64>>,
+  busy_chats: Mutex<HashSet<ChatKey>>,
   chat_threads_path: Option<PathBuf>,
+  // Named workspace registry and the workspace each chat is bound to (by name).
+  workspaces: RwLock<Vec<WorkspaceConfig>>,
+  chat_workspaces: Mutex<HashMap<ChatKey, String>>,
+  chat_workspaces_path: Option<PathBuf>,
+  // Per-chat model/effort/sandbox/approval choices and the owner's bounds for them.
+  chat_limits: RwLock<ChatLimits>,
+  chat_settings: Mutex<HashMap<ChatKey, CodexChatSettings>>,
+  chat_settings_path: Option<PathBuf>,
   // Local AI Hub's isolated Codex profile root (under app_data_dir).
   app_codex_home_dir: Option<PathBuf>,
   codex_tools_dir: Option<PathBuf>,
@@ -65,17 +106,47 @@ struct Inner {
 }
 
 struct PendingTurn {
-  chat_id: i64,
+  chat: ChatKey,
   thread_id: String,
   full_text: String,
   sent_byte: usize,
   updates_tx: mpsc::UnboundedSender<String>,
-  done: oneshot::Sender<Result<String, String>>,
+  progress_tx: watch::Sender<String>,
+  requests_tx: mpsc::UnboundedSender<CodexServerRequest>,
+  // Command/file-change items seen via item/started, used to describe approval requests.
+  items: HashMap<String, Value>,
+  // Safety deadline; pushed forward while the turn waits for the user.
+  deadline: tokio::time::Instant,
+  interrupted: bool,
+  done: oneshot::Sender<Result<CodexTurnResult, String>>,
+}
+
+struct PendingServerRequest {
+  rpc_id: Value,
+  turn_id: String,
+  created: tokio::time::Instant,
+  request: CodexServerRequest,
+}
+
+// Prefix of the error turns fail with when Codex has no signed-in account.
+const SIGN_IN_REQUIRED: &str = "Codex requires sign-in";
+
+pub fn is_sign_in_required(err: &str) -> bool {
+  err.starts_with(SIGN_IN_REQUIRED)
+}
+
+// Prefix of `set_chat_settings` errors for values outside the owner's limits.
+const NOT_ALLOWED: &str = "Not allowed by the chat limits";
+
+pub fn is_not_allowed(err: &str) -> bool {
+  err.starts_with(NOT_ALLOWED)
 }
 
 impl CodexRuntime {
   pub fn new(app: &AppHandle, logs: logbus::LogBus) -> Self {
-    let (chat_threads_path, chat_threads) = load_chat_threads(app);
+    let (chat_threads_path, chat_threads) = load_chat_map(paths::codex_chat_threads_path(app).ok());
+    let (chat_workspaces_path, chat_workspaces) = load_chat_map(paths::codex_chat_workspaces_path(app).ok());
+    let (chat_settings_path, chat_settings) = load_chat_map(paths::codex_chat_settings_path(app).ok());
     let app_codex_home_dir = paths::codex_home_dir(app).ok();
     let codex_tools_dir = paths::codex_tools_dir(app).ok();
     let app_global_agents_override = paths::codex_global_agents_override_path(app).ok();
@@ -93,11 +164,21 @@ impl CodexRuntime {
         install_lock: Mutex::new(()),
         next_id: AtomicU64::new(1),
         pending: Mutex::new(HashMap::new()),
+        login_waiters: Mutex::new(HashMap::new()),
         pending_turns: Mutex::new(HashMap::new()),
+        server_requests: Mutex::new(HashMap::new()),
+        next_request_id: AtomicU64::new(1),
+        approval_timeout_sec: RwLock::new(300),
         chat_threads: Mutex::new(chat_threads),
         resumed_threads: Mutex::new(HashSet::new()),
         busy_chats: Mutex::new(HashSet::new()),
         chat_threads_path,
+        workspaces: RwLock::new(vec![]),
+        chat_workspaces: Mutex::new(chat_workspaces),
+        chat_workspaces_path,
+        chat_limits: RwLock::new(ChatLimits::default()),
+        chat_settings: Mutex::new(chat_settings),
+        chat_settings_path,
         app_codex_home_dir,
         codex_tools_dir,
         app_global_agents_override,
@@ -145,6 +226,183 @@ impl CodexRuntime {
     self.prepare_codex_home().await;
   }
 
+  pub async fn set_workspaces(&self, workspaces: Vec<WorkspaceConfig>) {
+    let list = workspaces
+      .into_iter()
+      .filter(|w| !w.name.trim().is_empty() && !w.path.trim().is_empty())
+      .map(|w| WorkspaceConfig {
+        name: w.name.trim().to_string(),
+        path: w.path.trim().to_string(),
+        instructions: w.instructions,
+      })
+      .collect();
+    *self.inner.workspaces.write().await = list;
+    // The global override file only covers the workspace-independent part once workspaces exist.
+    self.prepare_codex_home().await;
+  }
+
+  pub async fn workspaces(&self) -> Vec<WorkspaceConfig> {
+    self.inner.workspaces.read().await.clone()
+  }
+
+  // Bound workspace of the chat, if it is still in the registry. A forum topic without its own
+  // binding uses its group's.
+  pub async fn chat_workspace(&self, chat: ChatKey) -> Option<WorkspaceConfig> {
+    let name = {
+      let bound = self.inner.chat_workspaces.lock().await;
+      bound.get(&chat).or_else(|| bound.get(&chat.whole_chat())).cloned()?
+    };
+    self.inner.workspaces.read().await.iter().find(|w| w.name == name).cloned()
+  }
+
+  // Binds the chat to a named workspace (None = back to the default folder). The chat's thread
+  // mapping is dropped so the next message starts a thread in the new folder.
+  pub async fn set_chat_workspace(&self, chat: ChatKey, name: Option<String>) -> Result<(), String> {
+    if let Some(name) = name.as_deref() {
+      let ws = self
+        .inner
+        .workspaces
+        .read()
+        .await
+        .iter()
+        .find(|w| w.name.eq_ignore_ascii_case(name.trim()))
+        .cloned()
+        .ok_or_else(|| format!("Unknown workspace: {name}"))?;
+      if !PathBuf::from(&ws.path).is_dir() {
+        return Err(format!("Workspace folder not found: {}", ws.path));
+      }
+      let mut guard = self.inner.chat_workspaces.lock().await;
+      guard.insert(chat, ws.name);
+      persist_chat_map(self.inner.chat_workspaces_path.as_ref(), &guard)?;
+    } else {
+      let mut guard = self.inner.chat_workspaces.lock().await;
+      guard.remove(&chat);
+      persist_chat_map(self.inner.chat_workspaces_path.as_ref(), &guard)?;
+    }
+
+    let mut threads = self.inner.chat_threads.lock().await;
+    if threads.remove(&chat).is_some() {
+      persist_chat_map(self.inner.chat_threads_path.as_ref(), &threads)?;
+    }
+    Ok(())
+  }
+
+  pub async fn set_chat_limits(&self, limits: ChatLimits) {
+    *self.inner.chat_limits.write().await = limits;
+  }
+
+  pub async fn chat_limits(&self) -> ChatLimits {
+    self.inner.chat_limits.read().await.clone()
+  }
+
+  // What the chat picked (unset fields = defaults), before clamping. A forum topic without its own
+  // settings uses its group's.
+  pub async fn chat_settings(&self, chat: ChatKey) -> CodexChatSettings {
+    let all = self.inner.chat_settings.lock().await;
+    all.get(&chat).or_else(|| all.get(&chat.whole_chat())).cloned().unwrap_or_default()
+  }
+
+  // What the chat's threads actually run with: its choices clamped to the current limits (the
+  // owner may have lowered them since). `sandbox` and `approval_policy` are always set.
+  pub async fn effective_chat_settings(&self, chat: Option<ChatKey>) -> CodexChatSettings {
+    let stored = match chat {
+      Some(c) => self.chat_settings(c).await,
+      None => CodexChatSettings::default(),
+    };
+    let limits = self.inner.chat_limits.read().await.clone();
+    let allowed = allowed_approval_policies(&limits);
+    CodexChatSettings {
+      model: stored.model.filter(|m| limits.models.contains(m)),
+      effort: stored.effort.map(|e| e.min(limits.max_effort)),
+      sandbox: Some(stored.sandbox.unwrap_or_default().min(limits.max_sandbox)),
+      approval_policy: Some(
+        stored
+          .approval_policy
+          .filter(|p| allowed.contains(p))
+          .or_else(|| allowed.contains(&ApprovalPolicy::Never).then_some(ApprovalPolicy::Never))
+          .unwrap_or(allowed[0]),
+      ),
+    }
+  }
+
+  // Stores the chat's choices; anything above the owner's limits is refused. Returns the
+  // effective settings. Applied from the next turn on.
+  pub async fn set_chat_settings(&self, chat: ChatKey, settings: CodexChatSettings) -> Result<CodexChatSettings, String> {
+    {
+      let limits = self.inner.chat_limits.read().await;
+      if let Some(m) = settings.model.as_ref().filter(|m| !limits.models.contains(m)) {
+        return Err(format!("{NOT_ALLOWED}: model {m}"));
+      }
+      if settings.effort.is_some_and(|e| e > limits.max_effort) {
+        return Err(format!("{NOT_ALLOWED}: reasoning effort above the maximum"));
+      }
+      if settings.sandbox.is_some_and(|s| s > limits.max_sandbox) {
+        return Err(format!("{NOT_ALLOWED}: sandbox mode above the maximum"));
+      }
+      if settings.approval_policy.is_some_and(|p| !allowed_approval_policies(&limits).contains(&p)) {
+        return Err(format!("{NOT_ALLOWED}: approval policy"));
+      }
+    }
+    {
+      let mut guard = self.inner.chat_settings.lock().await;
+      if settings == CodexChatSettings::default() {
+        guard.remove(&chat);
+      } else {
+        guard.insert(chat, settings);
+      }
+      persist_chat_map(self.inner.chat_settings_path.as_ref(), &guard)?;
+    }
+    Ok(self.effective_chat_settings(Some(chat)).await)
+  }
+
+  async fn cwd_for_chat(&self, chat: ChatKey) -> Option<String> {
+    match self.chat_workspace(chat).await {
+      Some(ws) => Some(ws.path),
+      None => self.inner.default_cwd.read().await.clone(),
+    }
+  }
+
+  // Adds the chat's approval policy, sandbox, model and cwd and, when named workspaces are
+  // configured, `developerInstructions` to thread/start, thread/resume and thread/fork params.
+  // AGENTS.override.md is global to the Codex profile, so anything that depends on the workspace
+  // has to travel with the thread instead.
+  async fn apply_thread_context(&self, chat: Option<ChatKey>, params: &mut Value) {
+    let settings = self.effective_chat_settings(chat).await;
+    params["approvalPolicy"] = serde_json::json!(settings.approval_policy);
+    params["sandbox"] = serde_json::json!(settings.sandbox);
+    if let Some(model) = settings.model {
+      params["model"] = Value::String(model);
+    }
+
+    let ws = match chat {
+      Some(c) => self.chat_workspace(c).await,
+      None => None,
+    };
+    let cwd = match &ws {
+      Some(w) => Some(w.path.clone()),
+      None => self.inner.default_cwd.read().await.clone(),
+    };
+    if let Some(cwd) = &cwd {
+      params["cwd"] = Value::String(cwd.clone());
+    }
+    if self.inner.workspaces.read().await.is_empty() {
+      return;
+    }
+
+    let mut parts: Vec<String> = vec![];
+    if let Some(w) = ws.as_ref().filter(|w| !w.instructions.trim().is_empty()) {
+      parts.push(w.instructions.trim().to_string());
+    }
+    let universal = self.inner.universal_instructions.read().await.clone();
+    let fallback_only = *self.inner.universal_fallback_only.read().await;
+    if fallback_only && !universal.trim().is_empty() && !workspace_has_agents(cwd.as_deref()) {
+      parts.push(universal.trim().to_string());
+    }
+    if !parts.is_empty() {
+      params["developerInstructions"] = Value::String(parts.join("\n\n"));
+    }
+  }
+
   pub async fn set_universal_instructions(&self, instructions: String, fallback_only: bool) {
     *self.inner.universal_instructions.write().await = instructions;
     *self.inner.universal_fallback_only.write().await = fallback_only;
@@ -152,6 +410,10 @@ impl CodexRuntime {
     self.prepare_codex_home().await;
   }
 
+  pub async fn set_approval_timeout(&self, secs: u64) {
+    *self.inner.approval_timeout_sec.write().await = secs.clamp(10, 3600);
+  }
+
   pub async fn status(&self) -> CodexStatus {
     self.inner.status.read().await.clone()
   }
@@ -173,7 +435,7 @@ impl CodexRuntime {
     {
       let mut threads = self.inner.chat_threads.lock().await;
       threads.clear();
-      persist_chat_threads(self.inner.chat_threads_path.as_ref(), &threads)?;
+      persist_chat_map(self.inner.chat_threads_path.as_ref(), &threads)?;
     }
 
     self
@@ -200,6 +462,8 @@ impl CodexRuntime {
       *child = None;
     }
     *self.inner.stdin.lock().await = None;
+    // A new app-server process has no threads loaded.
+    self.inner.resumed_threads.lock().await.clear();
     {
       let mut st = self.inner.status.write().await;
       st.running = false;
@@ -213,10 +477,17 @@ impl CodexRuntime {
     self.ensure_initialized().await
   }
 
+  pub async fn restart(&self) -> Result<(), String> {
+    self.stop().await?;
+    self.ensure_initialized().await
+  }
+
+  // `archived`: None lists everything, Some(x) only archived / only active threads.
   pub async fn list_threads(
     &self,
     limit: u32,
     cursor: Option<String>,
+    archived: Option<bool>,
   ) -> Result<CodexThreadListResponse, String> {
     self.ensure_initialized().await?;
     self.ensure_account_ready().await?;
@@ -225,8 +496,11 @@ impl CodexRuntime {
     // and the filter can accidentally exclude everything.
     let mut params = serde_json::json!({
       "limit": limit.clamp(1, 100),
-      "includeArchived": true
+      "includeArchived": archived != Some(false)
     });
+    if let Some(a) = archived {
+      params["archived"] = Value::Bool(a);
+    }
     if let Some(c) = cursor.and_then(|s| {
       let t = s.trim().to_string();
       if t.is_empty() { None } else { Some(t) }
@@ -272,7 +546,7 @@ impl CodexRuntime {
           .or_else(|| it.get("name").and_then(|v| v.as_str()))
           .map(|s| s.to_string());
 
-        let archived = it
+        let it_archived = it
           .get("archived")
           .and_then(|v| v.as_bool())
           .or_else(|| it.get("isArchived").and_then(|v| v.as_bool()))
@@ -305,13 +579,18 @@ impl CodexRuntime {
           .or_else(|| it.get("createdAt").and_then(|v| v.as_u64()).map(|s| s.saturating_mul(1000)))
           .or_else(|| it.get("created_at").and_then(|v| v.as_u64()).map(|s| s.saturating_mul(1000)));
 
+        // Older servers ignore the `archived` param; filter here as well.
+        if archived.is_some_and(|a| a != it_archived) {
+          continue;
+        }
+
         threads.push(super::types::CodexThreadSummary {
           id,
           title,
           preview,
           updated_at_unix_ms,
           created_at_unix_ms,
-          archived,
+          archived: it_archived,
           source_kind,
         });
       }
@@ -329,11 +608,11 @@ impl CodexRuntime {
     })
   }
 
-  pub async fn get_chat_thread(&self, chat_id: i64) -> Option<String> {
-    self.inner.chat_threads.lock().await.get(&chat_id).cloned()
+  pub async fn get_chat_thread(&self, chat: ChatKey) -> Option<String> {
+    self.inner.chat_threads.lock().await.get(&chat).cloned()
   }
 
-  pub async fn attach_chat_to_thread(&self, chat_id: i64, thread_id: String) -> Result<(), String> {
+  pub async fn attach_chat_to_thread(&self, chat: ChatKey, thread_id: String) -> Result<(), String> {
     self.ensure_initialized().await?;
     self.ensure_account_ready().await?;
 
@@ -345,8 +624,8 @@ impl CodexRuntime {
     // Persist mapping first so subsequent messages use this thread.
     {
       let mut guard = self.inner.chat_threads.lock().await;
-      guard.insert(chat_id, thread_id.clone());
-      persist_chat_threads(self.inner.chat_threads_path.as_ref(), &guard)?;
+      guard.insert(chat, thread_id.clone());
+      persist_chat_map(self.inner.chat_threads_path.as_ref(), &guard)?;
     }
 
     // Force resume on next use.
@@ -356,10 +635,71 @@ impl CodexRuntime {
     }
 
     // Validate/resume now so the user gets fast feedback.
-    self.resume_thread_if_needed(&thread_id).await?;
+    self.resume_thread_if_needed(&thread_id, chat).await?;
+    Ok(())
+  }
+
+  // Gives the chat a fresh thread; other chats keep theirs (unlike `reset_threads`).
+  pub async fn start_new_thread_for_chat(&self, chat: ChatKey) -> Result<String, String> {
+    self.ensure_initialized().await?;
+    self.ensure_account_ready().await?;
+    let thread_id = self.start_thread(chat).await?;
+    let mut guard = self.inner.chat_threads.lock().await;
+    guard.insert(chat, thread_id.clone());
+    persist_chat_map(self.inner.chat_threads_path.as_ref(), &guard)?;
+    Ok(thread_id)
+  }
+
+  pub async fn set_thread_name(&self, thread_id: &str, name: &str) -> Result<(), String> {
+    self.ensure_initialized().await?;
+    let name = name.trim();
+    if name.is_empty() {
+      return Err("Empty name".to_string());
+    }
+    let params = serde_json::json!({ "threadId": thread_id, "name": name });
+    self.send_request("thread/name/set", params).await?;
+    Ok(())
+  }
+
+  // Archiving also detaches the thread from every chat, so those chats start fresh next time.
+  pub async fn set_thread_archived(&self, thread_id: &str, archived: bool) -> Result<(), String> {
+    self.ensure_initialized().await?;
+    let method = if archived { "thread/archive" } else { "thread/unarchive" };
+    self
+      .inner
+      .logs
+      .push(logbus::LogLevel::Info, "codex", format!("{method} thread_id={thread_id}"));
+    self.send_request(method, serde_json::json!({ "threadId": thread_id })).await?;
+    if archived {
+      self.reset_thread_everywhere(thread_id).await;
+    }
     Ok(())
   }
 
+  // Copies the thread's history into a new thread and returns the new id.
+  pub async fn fork_thread(&self, thread_id: &str, chat: Option<ChatKey>) -> Result<String, String> {
+    self.ensure_initialized().await?;
+    self.ensure_account_ready().await?;
+    self
+      .inner
+      .logs
+      .push(logbus::LogLevel::Info, "codex", format!("thread/fork thread_id={thread_id}"));
+    let mut params = serde_json::json!({
+      "threadId": thread_id
+    });
+    self.apply_thread_context(chat, &mut params).await;
+    let res = self.send_request("thread/fork", params).await?;
+    let new_id = res
+      .get("thread")
+      .and_then(|t| t.get("id"))
+      .and_then(|id| id.as_str())
+      .ok_or_else(|| "thread/fork response missing thread.id".to_string())?
+      .to_string();
+    // The fork is loaded on the server already.
+    self.inner.resumed_threads.lock().await.insert(new_id.clone());
+    Ok(new_id)
+  }
+
   pub async fn doctor(&self) -> CodexDoctor {
     let local_entry = self.local_codex_entry_path();
     let local_codex_ok = local_entry.as_ref().map(|p| p.is_file()).unwrap_or(false);
@@ -492,6 +832,31 @@ impl CodexRuntime {
     Ok((auth_url, login_id))
   }
 
+  // Registers for `login_id`'s `account/login/completed`. Call it before handing out the auth URL,
+  // so a quick sign-in can't complete before anyone listens; then pass the receiver to
+  // `wait_for_login`.
+  pub async fn watch_login(&self, login_id: &str) -> oneshot::Receiver<Result<(), String>> {
+    let (tx, rx) = oneshot::channel();
+    self.inner.login_waiters.lock().await.insert(login_id.to_string(), tx);
+    rx
+  }
+
+  // Resolves when the sign-in started by `login_chatgpt` completes (Err on failure or timeout).
+  pub async fn wait_for_login(
+    &self,
+    login_id: &str,
+    rx: oneshot::Receiver<Result<(), String>>,
+    timeout: Duration,
+  ) -> Result<(), String> {
+    let res = tokio::time::timeout(timeout, rx).await;
+    self.inner.login_waiters.lock().await.remove(login_id);
+    match res {
+      Ok(Ok(r)) => r,
+      Ok(Err(_)) => Err("Login cancelled".to_string()),
+      Err(_) => Err("Login timed out".to_string()),
+    }
+  }
+
   pub async fn logout(&self) -> Result<(), String> {
     self.ensure_initialized().await?;
     let _ = self.send_request("account/logout", Value::Null).await?;
@@ -560,6 +925,7 @@ impl CodexRuntime {
           let _ = tx.send(Err("codex app-server stopped".to_string()));
         }
       }
+      inner.server_requests.lock().await.clear();
       {
         let mut turns = inner.pending_turns.lock().await;
         let items: Vec<(String, PendingTurn)> = turns.drain().collect();
@@ -567,7 +933,7 @@ impl CodexRuntime {
         for (_, p) in items {
           {
             let mut busy = inner.busy_chats.lock().await;
-            busy.remove(&p.chat_id);
+            busy.remove(&p.chat);
           }
           let _ = p.done.send(Err("Codex disconnected".to_string()));
         }
@@ -684,47 +1050,61 @@ impl CodexRuntime {
     Ok(())
   }
 
-  pub async fn start_turn_stream(&self, chat_id: i64, text: &str) -> Result<CodexStream, String> {
+  // `images` are local file paths sent as `localImage` inputs next to the text.
+  pub async fn start_turn_stream(&self, chat: ChatKey, text: &str, images: &[String]) -> Result<CodexStream, String> {
     let text = text.trim();
-    if text.is_empty() {
+    if text.is_empty() && images.is_empty() {
       return Err("Empty message".to_string());
     }
 
     {
       let mut busy = self.inner.busy_chats.lock().await;
-      if busy.contains(&chat_id) {
+      if busy.contains(&chat) {
         return Err("Busy".to_string());
       }
-      busy.insert(chat_id);
+      busy.insert(chat);
     }
 
     // If anything fails before we register the turn, make sure we clear the busy flag.
-    let started = self.start_turn_stream_inner(chat_id, text).await;
+    let started = self.start_turn_stream_inner(chat, text, images).await;
     if started.is_err() {
       let mut busy = self.inner.busy_chats.lock().await;
-      busy.remove(&chat_id);
+      busy.remove(&chat);
     }
     started
   }
 
-  async fn start_turn_stream_inner(&self, chat_id: i64, text: &str) -> Result<CodexStream, String> {
+  async fn start_turn_stream_inner(&self, chat: ChatKey, text: &str, images: &[String]) -> Result<CodexStream, String> {
     self.ensure_initialized().await?;
     self.ensure_account_ready().await?;
 
-    let thread_id = self.thread_for_chat(chat_id).await?;
+    let mut thread_id = self.thread_for_chat(chat).await?;
     self
       .inner
       .logs
-      .push(logbus::LogLevel::Info, "codex", format!("turn/start chat_id={chat_id}"));
+      .push(logbus::LogLevel::Info, "codex", format!("turn/start chat_id={chat}"));
 
+    let mut input: Vec<Value> = vec![];
+    if !text.is_empty() {
+      input.push(serde_json::json!({ "type": "text", "text": text }));
+    }
+    for path in images {
+      input.push(serde_json::json!({ "type": "localImage", "path": path }));
+    }
+    let settings = self.effective_chat_settings(Some(chat)).await;
     let mut params = serde_json::json!({
       "threadId": thread_id,
-      "approvalPolicy": "never",
-      "input": [
-        { "type": "text", "text": text }
-      ]
+      "approvalPolicy": settings.approval_policy,
+      "sandboxPolicy": sandbox_policy(settings.sandbox.unwrap_or_default()),
+      "input": input
     });
-    if let Some(cwd) = self.inner.default_cwd.read().await.clone() {
+    if let Some(model) = settings.model {
+      params["model"] = Value::String(model);
+    }
+    if let Some(effort) = settings.effort {
+      params["effort"] = serde_json::json!(effort);
+    }
+    if let Some(cwd) = self.cwd_for_chat(chat).await {
       params["cwd"] = Value::String(cwd);
     }
     let turn_start = match self.send_request("turn/start", params.clone()).await {
@@ -739,8 +1119,8 @@ impl CodexRuntime {
           // Use a full reset to handle any persistence/migration mismatches robustly.
           let _ = self.reset_threads().await;
           self.reset_thread_everywhere(&bad).await;
-          let new_thread_id = self.thread_for_chat(chat_id).await?;
-          params["threadId"] = Value::String(new_thread_id);
+          thread_id = self.thread_for_chat(chat).await?;
+          params["threadId"] = Value::String(thread_id.clone());
           self.send_request("turn/start", params).await?
         } else {
           return Err(e);
@@ -755,45 +1135,222 @@ impl CodexRuntime {
       .to_string();
 
     let (updates_tx, updates_rx) = mpsc::unbounded_channel::<String>();
-    let (done_tx, done_rx) = oneshot::channel::<Result<String, String>>();
+    let (requests_tx, requests_rx) = mpsc::unbounded_channel::<CodexServerRequest>();
+    let (done_tx, done_rx) = oneshot::channel::<Result<CodexTurnResult, String>>();
+    let (progress_tx, progress_rx) = watch::channel(String::new());
 
     {
       let mut turns = self.inner.pending_turns.lock().await;
       turns.insert(
         turn_id.clone(),
         PendingTurn {
-          chat_id,
+          chat,
           thread_id: thread_id.clone(),
           full_text: String::new(),
           sent_byte: 0,
           updates_tx,
+          progress_tx,
+          requests_tx,
+          items: HashMap::new(),
+          deadline: tokio::time::Instant::now() + Duration::from_secs(180),
+          interrupted: false,
           done: done_tx,
         },
       );
     }
 
     // Safety timeout: if we never get turn/completed, fail the turn so callers can stop waiting.
+    // Time spent waiting for the user to answer an approval doesn't count.
     let inner = self.inner.clone();
+    let watched_turn_id = turn_id.clone();
     tauri::async_runtime::spawn(async move {
-      tokio::time::sleep(Duration::from_secs(180)).await;
-      let mut turns = inner.pending_turns.lock().await;
-      if let Some(p) = turns.remove(&turn_id) {
-        {
-          let mut busy = inner.busy_chats.lock().await;
-          busy.remove(&p.chat_id);
+      let turn_id = watched_turn_id;
+      loop {
+        let deadline = {
+          let turns = inner.pending_turns.lock().await;
+          match turns.get(&turn_id) {
+            Some(p) => p.deadline,
+            None => return,
+          }
+        };
+        tokio::time::sleep_until(deadline).await;
+
+        let waiting = {
+          let reqs = inner.server_requests.lock().await;
+          reqs.values().any(|r| r.turn_id == turn_id)
+        };
+        let mut turns = inner.pending_turns.lock().await;
+        let Some(p) = turns.get(&turn_id) else { return; };
+        if waiting || p.deadline > tokio::time::Instant::now() {
+          if waiting {
+            drop(turns);
+            tokio::time::sleep(Duration::from_secs(1)).await;
+          }
+          continue;
         }
-        let _ = p.done.send(Err("Codex timeout".to_string()));
+        if let Some(p) = turns.remove(&turn_id) {
+          {
+            let mut busy = inner.busy_chats.lock().await;
+            busy.remove(&p.chat);
+          }
+          let _ = p.done.send(Err("Codex timeout".to_string()));
+        }
+        return;
       }
     });
 
-    Ok(CodexStream { updates_rx, done_rx })
+    Ok(CodexStream {
+      updates_rx,
+      done_rx,
+      requests_rx,
+      progress_rx,
+      thread_id,
+      turn_id,
+    })
+  }
+
+  // Drops `turn_id` from the chat's thread history, but only while it is still the latest turn.
+  // Files Codex changed during the turn stay as they are.
+  pub async fn rollback_turn(&self, chat: ChatKey, turn_id: &str) -> Result<(), String> {
+    self.ensure_initialized().await?;
+    self.ensure_account_ready().await?;
+
+    let thread_id = self.get_chat_thread(chat).await.ok_or_else(|| "No thread for chat".to_string())?;
+    let res = self
+      .send_request("thread/read", serde_json::json!({ "threadId": thread_id, "includeTurns": true }))
+      .await?;
+    let last = res
+      .get("thread")
+      .and_then(|t| t.get("turns"))
+      .and_then(|v| v.as_array())
+      .and_then(|turns| turns.last())
+      .and_then(|t| t.get("id"))
+      .and_then(|v| v.as_str())
+      .unwrap_or("");
+    if last != turn_id {
+      return Err("Not the latest turn of the thread".to_string());
+    }
+    self
+      .send_request("thread/rollback", serde_json::json!({ "threadId": thread_id, "numTurns": 1 }))
+      .await?;
+    self.inner.logs.push(
+      logbus::LogLevel::Info,
+      "codex",
+      format!("thread/rollback chat_id={chat} thread_id={thread_id} turn_id={turn_id}"),
+    );
+    Ok(())
+  }
+
+  // Stops the chat's running turn. Returns false when there is nothing to stop.
+  pub async fn interrupt_turn(&self, chat: ChatKey) -> Result<bool, String> {
+    let target = {
+      let mut turns = self.inner.pending_turns.lock().await;
+      turns.iter_mut().find(|(_, p)| p.chat == chat).map(|(turn_id, p)| {
+        p.interrupted = true;
+        (turn_id.clone(), p.thread_id.clone())
+      })
+    };
+    let Some((turn_id, thread_id)) = target else {
+      return Ok(false);
+    };
+
+    self
+      .inner
+      .logs
+      .push(logbus::LogLevel::Info, "codex", format!("turn/interrupt chat_id={chat}"));
+
+    // Don't leave the server blocked on approvals/questions nobody will answer now.
+    let stale: Vec<PendingServerRequest> = {
+      let mut reqs = self.inner.server_requests.lock().await;
+      let ids: Vec<u64> = reqs.iter().filter(|(_, r)| r.turn_id == turn_id).map(|(id, _)| *id).collect();
+      ids.into_iter().filter_map(|id| reqs.remove(&id)).collect()
+    };
+    for r in stale {
+      let result = match r.request {
+        CodexServerRequest::Approval(_) => serde_json::json!({ "decision": "decline" }),
+        CodexServerRequest::UserInput(_) => serde_json::json!({ "answers": {} }),
+      };
+      let _ = respond_server_request(&self.inner, r.rpc_id, result).await;
+    }
+
+    self
+      .send_request("turn/interrupt", serde_json::json!({ "threadId": thread_id, "turnId": turn_id }))
+      .await?;
+    Ok(true)
+  }
+
+  pub async fn pending_server_requests(&self) -> Vec<CodexServerRequest> {
+    let reqs = self.inner.server_requests.lock().await;
+    let mut out: Vec<(u64, CodexServerRequest)> = reqs.iter().map(|(id, r)| (*id, r.request.clone())).collect();
+    out.sort_by_key(|(id, _)| *id);
+    out.into_iter().map(|(_, r)| r).collect()
+  }
+
+  // `chat` is the caller's chat: request ids are global, a request can only be answered from its own chat.
+  pub async fn resolve_approval(
+    &self,
+    chat: ChatKey,
+    request_id: u64,
+    decision: CodexApprovalDecision,
+  ) -> Result<(), String> {
+    let req = {
+      let mut reqs = self.inner.server_requests.lock().await;
+      match reqs.get(&request_id) {
+        Some(r) if matches!(r.request, CodexServerRequest::Approval(_)) && r.request.chat() == chat => {
+          reqs.remove(&request_id)
+        }
+        _ => None,
+      }
+    };
+    let Some(req) = req else {
+      return Err("Approval request not found (already answered or expired)".to_string());
+    };
+    extend_turn_deadline(&self.inner, &req).await;
+    self.inner.logs.push(
+      logbus::LogLevel::Info,
+      "codex",
+      format!("approval request_id={request_id} -> {decision:?}"),
+    );
+    respond_server_request(&self.inner, req.rpc_id, serde_json::json!({ "decision": decision })).await
+  }
+
+  // Answers keyed by question id; each question may carry several answers (selected labels or free text).
+  pub async fn answer_user_input(
+    &self,
+    chat: ChatKey,
+    request_id: u64,
+    answers: HashMap<String, Vec<String>>,
+  ) -> Result<(), String> {
+    let req = {
+      let mut reqs = self.inner.server_requests.lock().await;
+      match reqs.get(&request_id) {
+        Some(r) if matches!(r.request, CodexServerRequest::UserInput(_)) && r.request.chat() == chat => {
+          reqs.remove(&request_id)
+        }
+        _ => None,
+      }
+    };
+    let Some(req) = req else {
+      return Err("Question not found (already answered or expired)".to_string());
+    };
+    extend_turn_deadline(&self.inner, &req).await;
+    self.inner.logs.push(
+      logbus::LogLevel::Info,
+      "codex",
+      format!("user input request_id={request_id} answered ({} questions)", answers.len()),
+    );
+    let mut out = serde_json::Map::new();
+    for (qid, list) in answers {
+      out.insert(qid, serde_json::json!({ "answers": list }));
+    }
+    respond_server_request(&self.inner, req.rpc_id, serde_json::json!({ "answers": out })).await
   }
 
   async fn ensure_account_ready(&self) -> Result<(), String> {
     // If Codex requires OpenAI auth and we have no account, fail fast with a user-friendly error.
     let (requires, have) = self.refresh_account_state().await.unwrap_or((false, false));
     if requires && !have {
-      return Err("Codex requires sign-in. Open AI Core -> Codex settings and sign in (ChatGPT).".to_string());
+      return Err(format!("{SIGN_IN_REQUIRED}. Open AI Core -> Codex settings and sign in (ChatGPT)."));
     }
     Ok(())
   }
@@ -830,9 +1387,9 @@ impl CodexRuntime {
     Ok((requires, have))
   }
 
-  async fn thread_for_chat(&self, chat_id: i64) -> Result<String, String> {
-    if let Some(t) = self.inner.chat_threads.lock().await.get(&chat_id).cloned() {
-      match self.resume_thread_if_needed(&t).await {
+  async fn thread_for_chat(&self, chat: ChatKey) -> Result<String, String> {
+    if let Some(t) = self.inner.chat_threads.lock().await.get(&chat).cloned() {
+      match self.resume_thread_if_needed(&t, chat).await {
         Ok(_) => return Ok(t),
         Err(e) => {
           if let Some(bad) = extract_no_rollout_thread_id(&e) {
@@ -856,31 +1413,32 @@ impl CodexRuntime {
     self
       .inner
       .logs
-      .push(logbus::LogLevel::Info, "codex", format!("thread/start chat_id={chat_id}"));
-    let mut params = serde_json::json!({
-      "approvalPolicy": "never",
-      "sandbox": "read-only"
-    });
-    if let Some(cwd) = self.inner.default_cwd.read().await.clone() {
-      params["cwd"] = Value::String(cwd);
-    }
-    let res = self.send_request("thread/start", params).await?;
-    let thread_id = res
-      .get("thread")
-      .and_then(|t| t.get("id"))
-      .and_then(|id| id.as_str())
-      .ok_or_else(|| "thread/start response missing thread.id".to_string())?
-      .to_string();
+      .push(logbus::LogLevel::Info, "codex", format!("thread/start chat_id={chat}"));
+    let thread_id = self.start_thread(chat).await?;
 
     {
       let mut guard = self.inner.chat_threads.lock().await;
-      guard.insert(chat_id, thread_id.clone());
-      persist_chat_threads(self.inner.chat_threads_path.as_ref(), &guard)?;
+      guard.insert(chat, thread_id.clone());
+      persist_chat_map(self.inner.chat_threads_path.as_ref(), &guard)?;
     }
     Ok(thread_id)
   }
 
-  async fn resume_thread_if_needed(&self, thread_id: &str) -> Result<(), String> {
+  async fn start_thread(&self, chat: ChatKey) -> Result<String, String> {
+    let mut params = serde_json::json!({});
+    self.apply_thread_context(Some(chat), &mut params).await;
+    let res = self.send_request("thread/start", params).await?;
+    Ok(
+      res
+        .get("thread")
+        .and_then(|t| t.get("id"))
+        .and_then(|id| id.as_str())
+        .ok_or_else(|| "thread/start response missing thread.id".to_string())?
+        .to_string(),
+    )
+  }
+
+  async fn resume_thread_if_needed(&self, thread_id: &str, chat: ChatKey) -> Result<(), String> {
     let thread_id = thread_id.to_string();
     {
       let resumed = self.inner.resumed_threads.lock().await;
@@ -895,13 +1453,9 @@ impl CodexRuntime {
       .push(logbus::LogLevel::Info, "codex", format!("thread/resume thread_id={}", thread_id));
     // Resume is required after server restart to load the stored thread context.
     let mut params = serde_json::json!({
-      "threadId": thread_id,
-      "approvalPolicy": "never",
-      "sandbox": "read-only"
+      "threadId": thread_id
     });
-    if let Some(cwd) = self.inner.default_cwd.read().await.clone() {
-      params["cwd"] = Value::String(cwd);
-    }
+    self.apply_thread_context(Some(chat), &mut params).await;
     let _ = self.send_request("thread/resume", params).await?;
 
     let mut resumed = self.inner.resumed_threads.lock().await;
@@ -1148,20 +1702,18 @@ impl CodexRuntime {
     let instructions = self.inner.universal_instructions.read().await.clone();
     let fallback_only = *self.inner.universal_fallback_only.read().await;
     let ws = self.inner.default_cwd.read().await.clone();
+    let has_named_workspaces = !self.inner.workspaces.read().await.is_empty();
 
     let should_apply = if instructions.trim().is_empty() {
       false
     } else if !fallback_only {
       true
+    } else if has_named_workspaces {
+      // Evaluated per thread instead (see `apply_thread_context`).
+      false
     } else {
       // Apply only when the workspace has no AGENTS files.
-      match ws {
-        None => true,
-        Some(dir) => {
-          let p = PathBuf::from(dir);
-          !(p.join("AGENTS.md").exists() || p.join("AGENTS.override.md").exists())
-        }
-      }
+      !workspace_has_agents(ws.as_deref())
     };
 
     let content = if should_apply { instructions } else { String::new() };
@@ -1413,7 +1965,7 @@ impl CodexRuntime {
     let mut removed_any = false;
     {
       let mut threads = self.inner.chat_threads.lock().await;
-      let keys: Vec<i64> = threads
+      let keys: Vec<ChatKey> = threads
         .iter()
         .filter_map(|(k, v)| if v == &thread_id { Some(*k) } else { None })
         .collect();
@@ -1422,7 +1974,7 @@ impl CodexRuntime {
         removed_any = true;
       }
       if removed_any {
-        let _ = persist_chat_threads(self.inner.chat_threads_path.as_ref(), &threads);
+        let _ = persist_chat_map(self.inner.chat_threads_path.as_ref(), &threads);
       }
     }
 
@@ -1450,28 +2002,31 @@ async fn handle_server_msg(inner: Arc<Inner>, msg: Value) {
     return;
   }
 
-  // Server request: { id, method, params } - we auto-decline approvals for now.
+  // Server request: { id, method, params }. Approvals and questions are forwarded to the chat that
+  // owns the turn; anything we can't route gets the safe default answer.
   if msg.get("id").is_some() && msg.get("method").is_some() && msg.get("result").is_none() {
     let id = msg.get("id").cloned().unwrap_or(Value::Null);
     let method = msg.get("method").and_then(|m| m.as_str()).unwrap_or("");
+    let params = msg.get("params").cloned().unwrap_or(Value::Null);
 
     let result = match method {
-      "item/commandExecution/requestApproval" => Some(serde_json::json!({ "decision": "decline" })),
-      "item/fileChange/requestApproval" => Some(serde_json::json!({ "decision": "decline" })),
-      "item/tool/requestUserInput" => Some(serde_json::json!({ "answers": {} })),
+      "item/commandExecution/requestApproval" | "item/fileChange/requestApproval" => {
+        if forward_server_request(inner.clone(), id.clone(), method, &params).await {
+          return;
+        }
+        Some(serde_json::json!({ "decision": "decline" }))
+      }
+      "item/tool/requestUserInput" => {
+        if forward_server_request(inner.clone(), id.clone(), method, &params).await {
+          return;
+        }
+        Some(serde_json::json!({ "answers": {} }))
+      }
       _ => None,
     };
 
     if let Some(result) = result {
-      let resp = serde_json::json!({ "id": id, "result": result });
-      let mut stdin = inner.stdin.lock().await;
-      if let Some(w) = stdin.as_mut() {
-        if let Ok(raw) = serde_json::to_string(&resp) {
-          let _ = w.write_all(raw.as_bytes()).await;
-          let _ = w.write_all(b"\n").await;
-          let _ = w.flush().await;
-        }
-      }
+      let _ = respond_server_request(&inner, id, result).await;
     }
     return;
   }
@@ -1494,15 +2049,25 @@ async fn handle_server_msg(inner: Arc<Inner>, msg: Value) {
       let login_id = params.get("loginId").and_then(|v| v.as_str()).map(|s| s.to_string());
       let err = params.get("error").and_then(|v| v.as_str()).map(|s| s.to_string());
 
-      let mut st = inner.status.write().await;
-      if let Some(id) = login_id {
-        if st.login_id.as_deref() == Some(id.as_str()) {
-          st.login_url = None;
-          st.login_id = None;
+      let err = err.unwrap_or_else(|| "Login failed".to_string());
+      {
+        let mut st = inner.status.write().await;
+        if let Some(id) = login_id.as_deref() {
+          if st.login_id.as_deref() == Some(id) {
+            st.login_url = None;
+            st.login_id = None;
+          }
+        }
+        if !success {
+          st.last_error = Some(err.clone());
         }
       }
-      if !success {
-        st.last_error = Some(err.unwrap_or_else(|| "Login failed".to_string()));
+      let waiter = match login_id {
+        Some(id) => inner.login_waiters.lock().await.remove(&id),
+        None => None,
+      };
+      if let Some(tx) = waiter {
+        let _ = tx.send(if success { Ok(()) } else { Err(err) });
       }
     }
     // Codex protocol has evolved; treat any item/*/delta that contains { turnId, delta } as assistant text.
@@ -1518,6 +2083,7 @@ async fn handle_server_msg(inner: Arc<Inner>, msg: Value) {
       let mut turns = inner.pending_turns.lock().await;
       if let Some(p) = turns.get_mut(&turn_id) {
         p.full_text.push_str(delta);
+        let _ = p.progress_tx.send(p.full_text.clone());
         flush_turn_chunks(p, false);
 
         // Best-effort: notify UI so an open thread can refresh or show "typing".
@@ -1527,6 +2093,20 @@ async fn handle_server_msg(inner: Arc<Inner>, msg: Value) {
         );
       }
     }
+    "item/started" => {
+      // Remember commands/file changes so approval requests can show what is being approved.
+      let turn_id = params.get("turnId").and_then(|v| v.as_str()).unwrap_or("").to_string();
+      let item = params.get("item").cloned().unwrap_or(Value::Null);
+      let ty = item.get("type").and_then(|t| t.as_str()).unwrap_or("");
+      if ty != "commandExecution" && ty != "fileChange" {
+        return;
+      }
+      let Some(item_id) = item.get("id").and_then(|v| v.as_str()).map(|s| s.to_string()) else { return; };
+      let mut turns = inner.pending_turns.lock().await;
+      if let Some(p) = turns.get_mut(&turn_id) {
+        p.items.insert(item_id, item);
+      }
+    }
     "item/completed" => {
       let turn_id = params.get("turnId").and_then(|v| v.as_str()).unwrap_or("").to_string();
       if turn_id.is_empty() {
@@ -1545,6 +2125,7 @@ async fn handle_server_msg(inner: Arc<Inner>, msg: Value) {
           // Prefer it over deltas (it can be more complete).
           if text.len() >= p.full_text.len() {
             p.full_text = text.to_string();
+            let _ = p.progress_tx.send(p.full_text.clone());
           }
           flush_turn_chunks(p, false);
         }
@@ -1564,19 +2145,26 @@ async fn handle_server_msg(inner: Arc<Inner>, msg: Value) {
         .and_then(|m| m.as_str())
         .map(|s| s.to_string());
 
+      {
+        // Unanswered approvals are moot once the turn is over.
+        let mut reqs = inner.server_requests.lock().await;
+        reqs.retain(|_, r| r.turn_id != turn_id);
+      }
+
       let mut turns = inner.pending_turns.lock().await;
       if let Some(mut p) = turns.remove(&turn_id) {
         flush_turn_chunks(&mut p, true);
         // clear per-chat busy state even if we fail to send back on the channel
         {
           let mut busy = inner.busy_chats.lock().await;
-          busy.remove(&p.chat_id);
+          busy.remove(&p.chat);
         }
 
         if status == "failed" {
           let _ = p.done.send(Err(err_msg.unwrap_or_else(|| "Turn failed".to_string())));
         } else {
-          let _ = p.done.send(Ok(p.full_text));
+          let interrupted = p.interrupted || status == "interrupted";
+          let _ = p.done.send(Ok(CodexTurnResult { text: p.full_text, interrupted }));
         }
 
         // Notify UI that a thread has new content. The UI can call thread/read to refresh.
@@ -1594,6 +2182,184 @@ async fn handle_server_msg(inner: Arc<Inner>, msg: Value) {
   }
 }
 
+async fn forward_server_request(inner: Arc<Inner>, rpc_id: Value, method: &str, params: &Value) -> bool {
+  let turn_id = params.get("turnId").and_then(|v| v.as_str()).unwrap_or("").to_string();
+  let item_id = params.get("itemId").and_then(|v| v.as_str()).unwrap_or("");
+  let request_id = inner.next_request_id.fetch_add(1, Ordering::SeqCst);
+
+  let req = {
+    let turns = inner.pending_turns.lock().await;
+    let Some(p) = turns.get(&turn_id) else { return false; };
+    let req = if method == "item/tool/requestUserInput" {
+      CodexServerRequest::UserInput(CodexUserInputRequest {
+        request_id,
+        chat_id: p.chat.chat_id,
+        topic_id: p.chat.topic_id,
+        thread_id: p.thread_id.clone(),
+        turn_id: turn_id.clone(),
+        questions: parse_user_input_questions(params),
+      })
+    } else {
+      let item = p.items.get(item_id).cloned().unwrap_or(Value::Null);
+      let kind = if method.starts_with("item/commandExecution/") { "commandExecution" } else { "fileChange" };
+      CodexServerRequest::Approval(CodexApprovalRequest {
+        request_id,
+        kind: kind.to_string(),
+        chat_id: p.chat.chat_id,
+        topic_id: p.chat.topic_id,
+        thread_id: p.thread_id.clone(),
+        turn_id: turn_id.clone(),
+        command: approval_command(params, &item),
+        cwd: params
+          .get("cwd")
+          .or_else(|| item.get("cwd"))
+          .and_then(|v| v.as_str())
+          .map(|s| s.to_string()),
+        reason: params.get("reason").and_then(|v| v.as_str()).map(|s| s.to_string()),
+        changes: approval_changes(params, &item),
+      })
+    };
+    inner.server_requests.lock().await.insert(
+      request_id,
+      PendingServerRequest {
+        rpc_id,
+        turn_id: turn_id.clone(),
+        created: tokio::time::Instant::now(),
+        request: req.clone(),
+      },
+    );
+    if p.requests_tx.send(req.clone()).is_err() {
+      inner.server_requests.lock().await.remove(&request_id);
+      return false;
+    }
+    req
+  };
+
+  inner.logs.push(
+    logbus::LogLevel::Info,
+    "codex",
+    format!("server request forwarded request_id={request_id} method={method}"),
+  );
+  let _ = inner.app.emit("codex://server_request", &req);
+
+  // Fall back to the safe default (decline / no answers) if nobody answers in time.
+  let timeout = *inner.approval_timeout_sec.read().await;
+  let inner2 = inner.clone();
+  tauri::async_runtime::spawn(async move {
+    tokio::time::sleep(Duration::from_secs(timeout)).await;
+    let Some(r) = inner2.server_requests.lock().await.remove(&request_id) else { return; };
+    extend_turn_deadline(&inner2, &r).await;
+    inner2.logs.push(
+      logbus::LogLevel::Warn,
+      "codex",
+      format!("server request request_id={request_id} timed out; answering with default"),
+    );
+    let result = match r.request {
+      CodexServerRequest::Approval(_) => serde_json::json!({ "decision": "decline" }),
+      CodexServerRequest::UserInput(_) => serde_json::json!({ "answers": {} }),
+    };
+    let _ = respond_server_request(&inner2, r.rpc_id, result).await;
+  });
+  true
+}
+
+fn parse_user_input_questions(params: &Value) -> Vec<CodexUserInputQuestion> {
+  // Shape: { questions: [{ id, header, question, isOther, isSecret, options: [{ label, description }] | null }] }
+  let Some(items) = params.get("questions").and_then(|v| v.as_array()) else { return vec![]; };
+  items
+    .iter()
+    .filter_map(|q| {
+      let id = q.get("id").and_then(|v| v.as_str())?.to_string();
+      let options = q
+        .get("options")
+        .and_then(|v| v.as_array())
+        .map(|opts| {
+          opts
+            .iter()
+            .filter_map(|o| {
+              Some(CodexUserInputOption {
+                label: o.get("label").and_then(|v| v.as_str())?.to_string(),
+                description: o.get("description").and_then(|v| v.as_str()).map(|s| s.to_string()),
+              })
+            })
+            .collect()
+        })
+        .unwrap_or_default();
+      Some(CodexUserInputQuestion {
+        id,
+        header: q.get("header").and_then(|v| v.as_str()).map(|s| s.to_string()),
+        question: q.get("question").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+        options,
+        is_other: q.get("isOther").and_then(|v| v.as_bool()).unwrap_or(false),
+        is_secret: q.get("isSecret").and_then(|v| v.as_bool()).unwrap_or(false),
+      })
+    })
+    .collect()
+}
+
+async fn extend_turn_deadline(inner: &Inner, req: &PendingServerRequest) {
+  let mut turns = inner.pending_turns.lock().await;
+  if let Some(p) = turns.get_mut(&req.turn_id) {
+    p.deadline += req.created.elapsed();
+  }
+}
+
+async fn respond_server_request(inner: &Inner, id: Value, result: Value) -> Result<(), String> {
+  let resp = serde_json::json!({ "id": id, "result": result });
+  let raw = serde_json::to_string(&resp).map_err(|e| format!("serialize failed: {e}"))?;
+  let mut stdin = inner.stdin.lock().await;
+  let w = stdin.as_mut().ok_or_else(|| "codex app-server not running".to_string())?;
+  w.write_all(raw.as_bytes())
+    .await
+    .map_err(|e| format!("write failed: {e}"))?;
+  w.write_all(b"\n")
+    .await
+    .map_err(|e| format!("write newline failed: {e}"))?;
+  w.flush().await.map_err(|e| format!("flush failed: {e}"))?;
+  Ok(())
+}
+
+fn approval_command(params: &Value, item: &Value) -> Option<String> {
+  let v = params.get("command").or_else(|| item.get("command"))?;
+  let cmd = match v {
+    Value::String(s) => s.clone(),
+    Value::Array(parts) => parts.iter().filter_map(|p| p.as_str()).collect::<Vec<_>>().join(" "),
+    _ => return None,
+  };
+  if cmd.trim().is_empty() { None } else { Some(cmd) }
+}
+
+fn approval_changes(params: &Value, item: &Value) -> Option<String> {
+  // Shape: { changes: [{ path, kind: { type: "add"|"delete"|"update" }, diff }] }
+  let changes = params
+    .get("changes")
+    .or_else(|| item.get("changes"))
+    .and_then(|v| v.as_array())?;
+  let mut out = String::new();
+  for c in changes {
+    let path = c.get("path").and_then(|v| v.as_str()).unwrap_or("?");
+    let kind = c
+      .get("kind")
+      .and_then(|k| k.get("type").and_then(|t| t.as_str()).or_else(|| k.as_str()))
+      .unwrap_or("update");
+    out.push_str(&format!("{kind} {path}\n"));
+    if let Some(diff) = c.get("diff").and_then(|v| v.as_str()) {
+      out.push_str(diff.trim_end());
+      out.push('\n');
+    }
+  }
+  let out = out.trim_end();
+  if out.is_empty() {
+    return None;
+  }
+  // Keep it short enough for a chat message; the full diff is in the thread.
+  if out.chars().count() > 1500 {
+    let short: String = out.chars().take(1500).collect();
+    return Some(format!("{short}…"));
+  }
+  Some(out.to_string())
+}
+
 async fn handle_codex_stderr(inner: Arc<Inner>, line: String) {
   // Keep a short tail for post-mortem when the process exits without a visible error.
   {
@@ -1629,7 +2395,7 @@ async fn handle_codex_stderr(inner: Arc<Inner>, line: String) {
     let mut removed_any = false;
     {
       let mut threads = inner.chat_threads.lock().await;
-      let keys: Vec<i64> = threads
+      let keys: Vec<ChatKey> = threads
         .iter()
         .filter_map(|(k, v)| if v == &thread_id { Some(*k) } else { None })
         .collect();
@@ -1638,7 +2404,7 @@ async fn handle_codex_stderr(inner: Arc<Inner>, line: String) {
         removed_any = true;
       }
       if removed_any {
-        let _ = persist_chat_threads(inner.chat_threads_path.as_ref(), &threads);
+        let _ = persist_chat_map(inner.chat_threads_path.as_ref(), &threads);
       }
     }
 
@@ -1738,18 +2504,32 @@ fn take_stream_chunk(full: &str, start_byte: usize, max_chars: usize, min_chars:
   }
 
   let end_byte = byte_index_at_char(rem, max_chars);
+  // A previous chunk may have been cut inside a code block.
+  let fences = FenceMap::new(None, full);
+  let open = fences.open_at(start_byte);
+  let in_code = |cut: usize| fences.inside(start_byte + cut);
 
   let desired_sentences = if min_chars <= 1 { 1 } else { 2 };
-  let cut = find_stream_cut(rem, end_byte.min(rem.len()), min_chars, desired_sentences, min_chars <= 1)?;
+  let cut = find_stream_cut(rem, &in_code, end_byte.min(rem.len()), min_chars, desired_sentences, min_chars <= 1)?;
   let raw = &rem[..cut];
   // Keep leading indentation (useful for code blocks), but drop trailing whitespace/newlines.
-  let chunk_text = raw.trim_end().to_string();
+  let mut chunk_text = raw.trim_end().to_string();
   let cut_byte = cut;
 
   if chunk_text.chars().count() < min_chars {
     return None;
   }
 
+  // Every chunk is rendered on its own: close a fence cut in half and reopen it in the next chunk.
+  if let Some(lang) = open {
+    chunk_text = format!("```{lang}\n{chunk_text}");
+  }
+  if in_code(cut) {
+    chunk_text.push_str("\n```");
+    // Cut at a line end: the next line's indentation is part of the code.
+    return Some((start_byte + cut_byte, chunk_text));
+  }
+
   // Advance, skipping whitespace so we don't get stuck on empty prefixes.
   let mut new_start = start_byte + cut_byte;
   while new_start < full.len() {
@@ -1832,6 +2612,7 @@ fn is_safe_chunk_boundary(rem: &str, cut: usize, force: bool) -> bool {
 
 fn find_stream_cut(
   rem: &str,
+  in_code: &dyn Fn(usize) -> bool,
   window_end: usize,
   min_chars: usize,
   desired_sentences: usize,
@@ -1839,9 +2620,14 @@ fn find_stream_cut(
 ) -> Option<usize> {
   let window = &rem[..window_end];
   let min_byte = byte_index_at_char(rem, min_chars.min(900));
+  // Cuts inside a code block only as a last resort (see step 5).
+  let outside_fence = |cut: usize| !in_code(cut);
 
   // 1) Prefer a paragraph break.
-  if let Some(idx) = window.rfind("\n\n") {
+  if let Some((idx, _)) = window
+    .rmatch_indices("\n\n")
+    .find(|(idx, _)| outside_fence(idx + 2))
+  {
     let cut = idx + 2;
     if cut >= min_byte && is_safe_chunk_boundary(rem, cut, force) {
       return Some(cut);
@@ -1852,7 +2638,7 @@ fn find_stream_cut(
   let list_markers = ["\n- ", "\n• ", "\n* ", "\n1. ", "\n2. ", "\n3. ", "\n4. "];
   let mut best_list_cut: Option<usize> = None;
   for m in list_markers {
-    if let Some(idx) = window.rfind(m) {
+    if let Some((idx, _)) = window.rmatch_indices(m).find(|(idx, _)| outside_fence(idx + 1)) {
       let cut = idx + 1; // keep '\n' at end of previous chunk
       if cut >= min_byte && is_safe_chunk_boundary(rem, cut, force) {
         best_list_cut = Some(best_list_cut.map(|b| b.max(cut)).unwrap_or(cut));
@@ -1878,7 +2664,7 @@ fn find_stream_cut(
     let mut candidate: Option<usize> = None;
     for (idx, end) in sentence_ends.iter().enumerate() {
       let have = idx + 1;
-      if have >= desired_sentences && *end >= min_byte && is_safe_chunk_boundary(rem, *end, force) {
+      if have >= desired_sentences && *end >= min_byte && is_safe_chunk_boundary(rem, *end, force) && outside_fence(*end) {
         candidate = Some(*end);
       }
     }
@@ -1891,7 +2677,7 @@ fn find_stream_cut(
   if window_end > 0 {
     let mut last_ws: Option<usize> = None;
     for (i, ch) in window.char_indices() {
-      if ch.is_whitespace() && i >= min_byte {
+      if ch.is_whitespace() && i >= min_byte && outside_fence(i) {
         last_ws = Some(i);
       }
     }
@@ -1902,7 +2688,18 @@ fn find_stream_cut(
     }
   }
 
-  // 5) As a last resort, only cut at the window edge when forcing (turn completed).
+  // 5) A code block longer than the window (or the turn is done): split it at a line end.
+  // The caller closes the fence and reopens it in the next chunk.
+  if force || window_end < rem.len() {
+    if let Some(idx) = window.rfind('\n') {
+      let cut = idx + 1;
+      if cut >= min_byte && !outside_fence(cut) {
+        return Some(cut);
+      }
+    }
+  }
+
+  // 6) As a last resort, only cut at the window edge when forcing (turn completed).
   if force && window_end >= min_byte && is_safe_chunk_boundary(rem, window_end, true) {
     return Some(window_end);
   }
@@ -1910,9 +2707,35 @@ fn find_stream_cut(
   None
 }
 
-fn load_chat_threads(app: &AppHandle) -> (Option<PathBuf>, HashMap<i64, String>) {
-  let path = paths::codex_chat_threads_path(app).ok();
-  let mut map = HashMap::<i64, String>::new();
+fn workspace_has_agents(dir: Option<&str>) -> bool {
+  match dir {
+    None => false,
+    Some(dir) => {
+      let p = PathBuf::from(dir);
+      p.join("AGENTS.md").exists() || p.join("AGENTS.override.md").exists()
+    }
+  }
+}
+
+fn allowed_approval_policies(limits: &ChatLimits) -> Vec<ApprovalPolicy> {
+  if limits.approval_policies.is_empty() {
+    vec![ApprovalPolicy::Never]
+  } else {
+    limits.approval_policies.clone()
+  }
+}
+
+// turn/start takes the sandbox as a policy object rather than the thread-level mode string.
+fn sandbox_policy(mode: SandboxMode) -> Value {
+  match mode {
+    SandboxMode::ReadOnly => serde_json::json!({ "type": "readOnly" }),
+    SandboxMode::WorkspaceWrite => serde_json::json!({ "type": "workspaceWrite" }),
+  }
+}
+
+// Per-chat state persisted as JSON maps keyed by chat (threads, workspaces, settings).
+fn load_chat_map<T: serde::de::DeserializeOwned>(path: Option<PathBuf>) -> (Option<PathBuf>, HashMap<ChatKey, T>) {
+  let mut map = HashMap::<ChatKey, T>::new();
   let Some(path2) = path.clone() else {
     return (None, map);
   };
@@ -1920,22 +2743,22 @@ fn load_chat_threads(app: &AppHandle) -> (Option<PathBuf>, HashMap<i64, String>)
     return (Some(path2), map);
   }
   match fs::read_to_string(&path2) {
-    Ok(raw) => match serde_json::from_str::<HashMap<i64, String>>(&raw) {
+    Ok(raw) => match serde_json::from_str::<HashMap<ChatKey, T>>(&raw) {
       Ok(m) => {
         map = m;
       }
       Err(e) => {
-        log::info!("codex: failed to parse chat threads state: {e}");
+        log::info!("codex: failed to parse chat state {}: {e}", path2.display());
       }
     },
     Err(e) => {
-      log::info!("codex: failed to read chat threads state: {e}");
+      log::info!("codex: failed to read chat state {}: {e}", path2.display());
     }
   }
   (Some(path2), map)
 }
 
-fn persist_chat_threads(path: Option<&PathBuf>, map: &HashMap<i64, String>) -> Result<(), String> {
+fn persist_chat_map<T: serde::Serialize>(path: Option<&PathBuf>, map: &HashMap<ChatKey, T>) -> Result<(), String> {
   let Some(path) = path else { return Ok(()); };
   if let Some(parent) = path.parent() {
     fs::create_dir_all(parent).map_err(|e| format!("create codex state dir failed: {e}"))?;
@@ -1,3 +1,66 @@
+use std::{fmt, str::FromStr};
+
+use crate::core::config_store::{ApprovalPolicy, ReasoningEffort, SandboxMode};
+
+// A conversation with its own Codex thread, workspace and settings: a chat, or one forum topic in
+// it. Persisted maps use the text form ("<chat>" or "<chat>:<topic>") as the key, so files written
+// before topics existed still load.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChatKey {
+  pub chat_id: i64,
+  pub topic_id: Option<i64>,
+}
+
+impl ChatKey {
+  pub fn new(chat_id: i64, topic_id: Option<i64>) -> Self {
+    Self { chat_id, topic_id }
+  }
+
+  // The chat as a whole (for a topic: the group it belongs to).
+  pub fn whole_chat(self) -> Self {
+    Self::new(self.chat_id, None)
+  }
+}
+
+impl From<i64> for ChatKey {
+  fn from(chat_id: i64) -> Self {
+    Self::new(chat_id, None)
+  }
+}
+
+impl fmt::Display for ChatKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.topic_id {
+      Some(topic) => write!(f, "{}:{topic}", self.chat_id),
+      None => write!(f, "{}", self.chat_id),
+    }
+  }
+}
+
+impl FromStr for ChatKey {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let bad = |_| format!("bad chat key: {s}");
+    match s.split_once(':') {
+      Some((chat, topic)) => Ok(Self::new(chat.parse().map_err(bad)?, Some(topic.parse().map_err(bad)?))),
+      None => Ok(Self::new(s.parse().map_err(bad)?, None)),
+    }
+  }
+}
+
+impl serde::Serialize for ChatKey {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> serde::Deserialize<'de> for ChatKey {
+  fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+  }
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, Default)]
 pub struct CodexStatus {
   pub running: bool,
@@ -73,3 +136,104 @@ pub struct CodexThreadReadResponse {
   #[serde(default)]
   pub items: Vec<CodexTranscriptItem>,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CodexApprovalDecision {
+  Accept,
+  AcceptForSession,
+  Decline,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodexApprovalRequest {
+  // Local id used to answer the request (not the JSON-RPC id).
+  pub request_id: u64,
+  // "commandExecution" or "fileChange".
+  pub kind: String,
+  pub chat_id: i64,
+  #[serde(default)]
+  pub topic_id: Option<i64>,
+  pub thread_id: String,
+  pub turn_id: String,
+  #[serde(default)]
+  pub command: Option<String>,
+  #[serde(default)]
+  pub cwd: Option<String>,
+  #[serde(default)]
+  pub reason: Option<String>,
+  // Best-effort summary of the proposed file changes (paths + truncated diff).
+  #[serde(default)]
+  pub changes: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodexUserInputOption {
+  pub label: String,
+  #[serde(default)]
+  pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodexUserInputQuestion {
+  pub id: String,
+  #[serde(default)]
+  pub header: Option<String>,
+  pub question: String,
+  // Empty for free-text questions.
+  #[serde(default)]
+  pub options: Vec<CodexUserInputOption>,
+  // Free text is allowed in addition to the options.
+  #[serde(default)]
+  pub is_other: bool,
+  #[serde(default)]
+  pub is_secret: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodexUserInputRequest {
+  pub request_id: u64,
+  pub chat_id: i64,
+  #[serde(default)]
+  pub topic_id: Option<i64>,
+  pub thread_id: String,
+  pub turn_id: String,
+  pub questions: Vec<CodexUserInputQuestion>,
+}
+
+// Server-initiated requests that need an answer from the user who owns the turn.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum CodexServerRequest {
+  Approval(CodexApprovalRequest),
+  UserInput(CodexUserInputRequest),
+}
+
+impl CodexServerRequest {
+  // The chat whose turn asked; only that chat may answer.
+  pub fn chat(&self) -> ChatKey {
+    match self {
+      Self::Approval(r) => ChatKey::new(r.chat_id, r.topic_id),
+      Self::UserInput(r) => ChatKey::new(r.chat_id, r.topic_id),
+    }
+  }
+}
+
+// Per-chat Codex settings picked in Telegram (/settings) or the UI. Unset = default; everything is
+// clamped to the owner's `ChatLimits` before it reaches Codex.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodexChatSettings {
+  #[serde(default)]
+  pub model: Option<String>,
+  #[serde(default)]
+  pub effort: Option<ReasoningEffort>,
+  #[serde(default)]
+  pub sandbox: Option<SandboxMode>,
+  #[serde(default)]
+  pub approval_policy: Option<ApprovalPolicy>,
+}
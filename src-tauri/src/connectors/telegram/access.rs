@@ -0,0 +1,73 @@
+use crate::core::config_store::{ApprovalPolicy, Role, SandboxMode, TelegramConfig};
+
+// Who may do what. Every update is checked against these tables once, in the runtime's dispatch,
+// before any command or Codex prompt runs.
+
+// Highest role among the rules matching this user in this chat; None = no access at all.
+pub(super) fn role_for(cfg: &TelegramConfig, user_id: Option<i64>, chat_id: i64) -> Option<Role> {
+  let rules = cfg.access.iter().filter(|r| {
+    let user_ok = match r.user_id {
+      Some(u) => user_id == Some(u),
+      None => true,
+    };
+    let chat_ok = r.chat_id.is_none_or(|c| c == chat_id);
+    (r.user_id.is_some() || r.chat_id.is_some()) && user_ok && chat_ok
+  });
+  let legacy = cfg.allowed_chat_ids.contains(&chat_id).then_some(Role::Operator);
+  rules.map(|r| r.role).chain(legacy).max()
+}
+
+// Minimum role for a command; None = anyone. Commands not listed here are owner-only, so a new
+// command has to be placed in this table deliberately.
+pub(super) fn command_role(cmd: &str) -> Option<Role> {
+  match cmd {
+    "/start" | "/whoami" | "/pair" | "/request_access" => None,
+    "/ping" | "/threads" => Some(Role::ReadOnly),
+    "/codex" | "/stop" | "/thread" | "/new" | "/rename" | "/fork" | "/stream" | "/settings" | "/queue" => {
+      Some(Role::User)
+    }
+    "/archive" | "/unarchive" | "/workspace" => Some(Role::Operator),
+    _ => Some(Role::Owner),
+  }
+}
+
+// Minimum role for an inline button, by its callback data.
+pub(super) fn callback_role(data: &str) -> Role {
+  let parts: Vec<&str> = data.split(':').collect();
+  match parts.as_slice() {
+    ["tp", ..] => Role::ReadOnly,
+    ["ap", ..] | ["ws", ..] => Role::Operator,
+    ["st", "s", v] => parse(v).map(sandbox_role).unwrap_or(Role::Owner),
+    ["st", "a", v] => parse(v).map(approval_role).unwrap_or(Role::Owner),
+    _ => Role::User,
+  }
+}
+
+// Sending a prompt to a chat needs the role that the chat's sandbox and approval policy require.
+pub(super) fn sandbox_role(mode: SandboxMode) -> Role {
+  match mode {
+    SandboxMode::ReadOnly => Role::User,
+    SandboxMode::WorkspaceWrite => Role::Operator,
+  }
+}
+
+// Any policy but `never` lets the chat approve commands outside the sandbox.
+pub(super) fn approval_role(policy: ApprovalPolicy) -> Role {
+  match policy {
+    ApprovalPolicy::Never => Role::User,
+    ApprovalPolicy::OnRequest | ApprovalPolicy::Untrusted => Role::Operator,
+  }
+}
+
+pub(super) fn role_label(role: Role) -> &'static str {
+  match role {
+    Role::ReadOnly => "read-only",
+    Role::User => "user",
+    Role::Operator => "operator",
+    Role::Owner => "owner",
+  }
+}
+
+fn parse<T: serde::de::DeserializeOwned>(s: &str) -> Option<T> {
+  serde_json::from_value(serde_json::Value::String(s.to_string())).ok()
+}
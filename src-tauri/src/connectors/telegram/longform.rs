@@ -0,0 +1,112 @@
+// Long answers as files: a short summary for the chat, the full answer as Markdown and big fenced
+// code blocks as files of their own.
+
+#[derive(Debug, Clone)]
+pub(super) struct Document {
+  pub name: String,
+  pub body: String,
+}
+
+// The prose before the first code block, cut to `max_chars` at a paragraph or sentence end.
+pub(super) fn summary(md: &str, max_chars: usize) -> String {
+  let md = md.replace("\r\n", "\n");
+  let prose: Vec<&str> = md.split('\n').take_while(|l| !l.trim_start().starts_with("```")).collect();
+  let prose = prose.join("\n");
+  let prose = prose.trim();
+  if prose.chars().count() <= max_chars {
+    return prose.to_string();
+  }
+
+  let mut out = String::new();
+  for para in prose.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+    let sep = if out.is_empty() { 0 } else { 2 };
+    if out.chars().count() + sep + para.chars().count() > max_chars {
+      break;
+    }
+    if sep > 0 {
+      out.push_str("\n\n");
+    }
+    out.push_str(para);
+  }
+  if out.is_empty() {
+    // One long paragraph: end at the last sentence that fits, or just cut.
+    let head: String = prose.chars().take(max_chars).collect();
+    let cut = head
+      .rfind(|c: char| matches!(c, '.' | '!' | '?' | '\n'))
+      .filter(|&i| i >= head.len() / 3)
+      .map(|i| i + 1)
+      .unwrap_or(head.len());
+    out = head[..cut].trim_end().to_string();
+  }
+  out.push('…');
+  out
+}
+
+// Fenced code blocks of at least `min_chars` chars, as `snippet-<n>.<ext>` by the fence language.
+pub(super) fn code_files(md: &str, min_chars: usize) -> Vec<Document> {
+  if min_chars == 0 {
+    return vec![];
+  }
+  let md = md.replace("\r\n", "\n");
+  let lines: Vec<&str> = md.split('\n').collect();
+  let mut out = vec![];
+  let mut i = 0usize;
+  while i < lines.len() {
+    let Some(info) = lines[i].trim_start().strip_prefix("```") else {
+      i += 1;
+      continue;
+    };
+    // Same fence rules as the renderer: an unterminated fence runs to the end of the text.
+    let lang = info.split_whitespace().next().unwrap_or("").to_ascii_lowercase();
+    let start = i + 1;
+    i = start;
+    while i < lines.len() && !lines[i].trim_start().starts_with("```") {
+      i += 1;
+    }
+    let body = lines[start..i].join("\n");
+    i += 1;
+    if body.chars().count() >= min_chars {
+      let name = format!("snippet-{}.{}", out.len() + 1, extension(&lang));
+      out.push(Document { name, body: body + "\n" });
+    }
+  }
+  out
+}
+
+fn extension(lang: &str) -> &'static str {
+  match lang {
+    "rust" | "rs" => "rs",
+    "python" | "py" => "py",
+    "javascript" | "js" | "node" => "js",
+    "typescript" | "ts" => "ts",
+    "tsx" => "tsx",
+    "jsx" => "jsx",
+    "json" => "json",
+    "bash" | "sh" | "shell" | "zsh" | "console" => "sh",
+    "powershell" | "ps1" => "ps1",
+    "yaml" | "yml" => "yml",
+    "toml" => "toml",
+    "html" => "html",
+    "css" => "css",
+    "scss" => "scss",
+    "sql" => "sql",
+    "go" | "golang" => "go",
+    "java" => "java",
+    "kotlin" | "kt" => "kt",
+    "swift" => "swift",
+    "c" => "c",
+    "h" => "h",
+    "cpp" | "c++" | "cc" | "cxx" => "cpp",
+    "csharp" | "cs" | "c#" => "cs",
+    "ruby" | "rb" => "rb",
+    "php" => "php",
+    "lua" => "lua",
+    "diff" | "patch" => "diff",
+    "markdown" | "md" => "md",
+    "xml" => "xml",
+    "dockerfile" | "docker" => "dockerfile",
+    "makefile" | "make" => "mk",
+    "ini" => "ini",
+    _ => "txt",
+  }
+}
@@ -0,0 +1,316 @@
+use serde_json::Value;
+
+// Markdown (as Codex writes it) -> plain text + Telegram `entities`.
+// Entities avoid MarkdownV2 escaping entirely: whatever we can't parse is left as literal text.
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum EntityKind {
+  Bold,
+  Italic,
+  Strikethrough,
+  Code,
+  Pre(Option<String>),
+  TextLink(String),
+}
+
+// Offsets are in chars here and converted to UTF-16 code units when serialized.
+#[derive(Debug, Clone)]
+pub(super) struct Entity {
+  kind: EntityKind,
+  offset: usize,
+  length: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct Rendered {
+  pub text: String,
+  entities: Vec<Entity>,
+}
+
+impl Rendered {
+  pub(super) fn has_entities(&self) -> bool {
+    !self.entities.is_empty()
+  }
+
+  pub(super) fn entities_json(&self) -> Value {
+    // char index -> UTF-16 offset
+    let mut utf16_at: Vec<usize> = Vec::with_capacity(self.text.chars().count() + 1);
+    let mut acc = 0usize;
+    for ch in self.text.chars() {
+      utf16_at.push(acc);
+      acc += ch.len_utf16();
+    }
+    utf16_at.push(acc);
+
+    let items: Vec<Value> = self
+      .entities
+      .iter()
+      .map(|e| {
+        let start = utf16_at[e.offset];
+        let end = utf16_at[e.offset + e.length];
+        let mut v = serde_json::json!({ "offset": start, "length": end - start });
+        match &e.kind {
+          EntityKind::Bold => v["type"] = "bold".into(),
+          EntityKind::Italic => v["type"] = "italic".into(),
+          EntityKind::Strikethrough => v["type"] = "strikethrough".into(),
+          EntityKind::Code => v["type"] = "code".into(),
+          EntityKind::Pre(lang) => {
+            v["type"] = "pre".into();
+            if let Some(lang) = lang {
+              v["language"] = lang.clone().into();
+            }
+          }
+          EntityKind::TextLink(url) => {
+            v["type"] = "text_link".into();
+            v["url"] = url.clone().into();
+          }
+        }
+        v
+      })
+      .collect();
+    Value::Array(items)
+  }
+
+  // Splits into messages of at most `max_chars`, preferring line breaks; entities are clipped to
+  // each part (a code block cut in two becomes two code blocks).
+  pub(super) fn split(&self, max_chars: usize) -> Vec<Rendered> {
+    let chars: Vec<char> = self.text.chars().collect();
+    if chars.len() <= max_chars {
+      return vec![self.clone()];
+    }
+
+    let mut out = vec![];
+    let mut start = 0usize;
+    while start < chars.len() {
+      let mut end = (start + max_chars).min(chars.len());
+      if end < chars.len() {
+        if let Some(nl) = (start + max_chars / 2..end).rev().find(|&i| chars[i] == '\n') {
+          end = nl + 1;
+        }
+      }
+      let text: String = chars[start..end].iter().collect();
+      let entities = self
+        .entities
+        .iter()
+        .filter_map(|e| {
+          let s = e.offset.max(start);
+          let t = (e.offset + e.length).min(end);
+          (s < t).then(|| Entity {
+            kind: e.kind.clone(),
+            offset: s - start,
+            length: t - s,
+          })
+        })
+        .collect();
+      out.push(Rendered { text, entities });
+      start = end;
+    }
+    out
+  }
+}
+
+pub(super) fn render(md: &str) -> Rendered {
+  let md = md.replace("\r\n", "\n");
+  let lines: Vec<&str> = md.split('\n').collect();
+  let mut out = Builder::default();
+
+  let mut i = 0usize;
+  while i < lines.len() {
+    let line = lines[i];
+    let trimmed = line.trim_start();
+
+    if let Some(info) = trimmed.strip_prefix("```") {
+      // Fenced code block; an unterminated fence runs to the end of the text.
+      let lang = info.split_whitespace().next().map(|s| s.to_string());
+      let mut body: Vec<&str> = vec![];
+      i += 1;
+      while i < lines.len() && !lines[i].trim_start().starts_with("```") {
+        body.push(lines[i]);
+        i += 1;
+      }
+      i += 1; // closing fence
+      let code = body.join("\n");
+      if !code.is_empty() {
+        let start = out.len;
+        out.push_str(&code);
+        out.entity(EntityKind::Pre(lang), start);
+      }
+      if i < lines.len() {
+        out.push_str("\n");
+      }
+      continue;
+    }
+
+    let indent = &line[..line.len() - trimmed.len()];
+    if let Some(title) = heading_text(trimmed) {
+      let start = out.len;
+      inline(&mut out, title);
+      out.entity(EntityKind::Bold, start);
+    } else if let Some(rest) = ["- ", "* ", "+ "].iter().find_map(|m| trimmed.strip_prefix(m)) {
+      out.push_str(indent);
+      out.push_str("• ");
+      inline(&mut out, rest);
+    } else {
+      out.push_str(indent);
+      inline(&mut out, trimmed);
+    }
+    if i + 1 < lines.len() {
+      out.push_str("\n");
+    }
+    i += 1;
+  }
+
+  out.finish()
+}
+
+fn heading_text(line: &str) -> Option<&str> {
+  let hashes = line.chars().take_while(|c| *c == '#').count();
+  if hashes == 0 || hashes > 6 {
+    return None;
+  }
+  line[hashes..].strip_prefix(' ').map(|t| t.trim().trim_end_matches('#').trim_end())
+}
+
+#[derive(Default)]
+struct Builder {
+  text: String,
+  len: usize,
+  entities: Vec<Entity>,
+}
+
+impl Builder {
+  fn push_str(&mut self, s: &str) {
+    self.text.push_str(s);
+    self.len += s.chars().count();
+  }
+
+  fn push(&mut self, c: char) {
+    self.text.push(c);
+    self.len += 1;
+  }
+
+  fn entity(&mut self, kind: EntityKind, start: usize) {
+    if self.len > start {
+      self.entities.push(Entity {
+        kind,
+        offset: start,
+        length: self.len - start,
+      });
+    }
+  }
+
+  fn finish(self) -> Rendered {
+    let mut entities = self.entities;
+    entities.sort_by_key(|e| (e.offset, std::cmp::Reverse(e.length)));
+    Rendered {
+      text: self.text,
+      entities,
+    }
+  }
+}
+
+// Inline spans within a single line: `code`, [text](url), **bold**, __bold__, *italic*, _italic_,
+// ~~strike~~ and backslash escapes. Unmatched markers stay as literal text.
+fn inline(out: &mut Builder, s: &str) {
+  let chars: Vec<char> = s.chars().collect();
+  let mut i = 0usize;
+  while i < chars.len() {
+    let c = chars[i];
+
+    if c == '\\' && i + 1 < chars.len() && chars[i + 1].is_ascii_punctuation() {
+      out.push(chars[i + 1]);
+      i += 2;
+      continue;
+    }
+
+    if c == '`' {
+      if let Some(end) = find_from(&chars, i + 1, &['`']) {
+        let start = out.len;
+        out.push_str(&chars[i + 1..end].iter().collect::<String>());
+        out.entity(EntityKind::Code, start);
+        i = end + 1;
+        continue;
+      }
+    }
+
+    if c == '[' {
+      if let Some((label_end, url_end)) = link_at(&chars, i) {
+        let url: String = chars[label_end + 2..url_end].iter().collect();
+        let start = out.len;
+        inline(out, &chars[i + 1..label_end].iter().collect::<String>());
+        out.entity(EntityKind::TextLink(url.trim().to_string()), start);
+        i = url_end + 1;
+        continue;
+      }
+    }
+
+    let pair = [c, *chars.get(i + 1).unwrap_or(&' ')];
+    let double = match pair {
+      ['*', '*'] | ['_', '_'] => Some(EntityKind::Bold),
+      ['~', '~'] => Some(EntityKind::Strikethrough),
+      _ => None,
+    };
+    if let Some(kind) = double {
+      if opens(&chars, i, 2) {
+        if let Some(end) = closing(&chars, i + 2, &pair) {
+          let start = out.len;
+          inline(out, &chars[i + 2..end].iter().collect::<String>());
+          out.entity(kind, start);
+          i = end + 2;
+          continue;
+        }
+      }
+    }
+
+    if (c == '*' || c == '_') && opens(&chars, i, 1) {
+      if let Some(end) = closing(&chars, i + 1, &[c]) {
+        let start = out.len;
+        inline(out, &chars[i + 1..end].iter().collect::<String>());
+        out.entity(EntityKind::Italic, start);
+        i = end + 1;
+        continue;
+      }
+    }
+
+    out.push(c);
+    i += 1;
+  }
+}
+
+fn find_from(chars: &[char], from: usize, pat: &[char]) -> Option<usize> {
+  (from..chars.len().saturating_sub(pat.len() - 1)).find(|&j| chars[j..j + pat.len()] == *pat)
+}
+
+// A marker opens a span when it is not glued to a word on the left and is followed by text,
+// so `snake_case` and `2 * 3` stay literal.
+fn opens(chars: &[char], i: usize, width: usize) -> bool {
+  let before_ok = i == 0 || !chars[i - 1].is_alphanumeric();
+  let after = chars.get(i + width);
+  before_ok && after.is_some_and(|c| !c.is_whitespace())
+}
+
+fn closing(chars: &[char], from: usize, pat: &[char]) -> Option<usize> {
+  let mut j = from;
+  while let Some(end) = find_from(chars, j, pat) {
+    let before_ok = end > from && !chars[end - 1].is_whitespace();
+    let after_ok = chars.get(end + pat.len()).is_none_or(|c| !c.is_alphanumeric());
+    if before_ok && after_ok {
+      return Some(end);
+    }
+    j = end + 1;
+  }
+  None
+}
+
+// `[label](url)` starting at `i`; returns the index of `]` and of `)`.
+fn link_at(chars: &[char], i: usize) -> Option<(usize, usize)> {
+  let label_end = find_from(chars, i + 1, &[']'])?;
+  if chars.get(label_end + 1) != Some(&'(') || label_end == i + 1 {
+    return None;
+  }
+  let url_end = find_from(chars, label_end + 2, &[')'])?;
+  let url: String = chars[label_end + 2..url_end].iter().collect();
+  let url = url.trim();
+  let ok = url.starts_with("http://") || url.starts_with("https://") || url.starts_with("tg://");
+  (ok && !url.contains(char::is_whitespace)).then_some((label_end, url_end))
+}
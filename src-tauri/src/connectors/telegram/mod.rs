@@ -1,3 +1,7 @@
+pub mod access;
+pub mod longform;
+pub mod markdown;
 pub mod runtime;
 pub mod self_test;
 pub mod types;
+pub mod webhook;
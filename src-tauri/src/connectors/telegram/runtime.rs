@@ -1,16 +1,96 @@
-use std::{collections::HashMap, error::Error, fs, sync::Arc, time::Duration};
+use std::{
+  collections::{HashMap, VecDeque},
+  error::Error,
+  fs,
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::Duration,
+};
 
 use reqwest::Client;
 use serde::Deserialize;
-use tauri::AppHandle;
-use tokio::sync::{watch, RwLock};
+use tauri::{AppHandle, Emitter};
+use tokio::{
+  io::AsyncWriteExt,
+  sync::{mpsc, watch, Mutex, RwLock},
+};
 
-use crate::core::{config_store::AppConfig, logbus, paths, secrets, time};
-use crate::connectors::codex::runtime::CodexRuntime;
+use crate::core::{
+  config_store::{
+    self,
+    AccessRule,
+    AppConfig,
+    ApprovalPolicy,
+    BusyPolicy,
+    ReasoningEffort,
+    Role,
+    SandboxMode,
+    StreamMode,
+    TelegramConfig,
+    TelegramMode,
+  },
+  fences::FenceMap,
+  logbus, paths, secrets, stt, time,
+};
+use crate::connectors::codex::{
+  runtime::{self as codex_runtime, CodexRuntime},
+  types::{ChatKey, CodexApprovalDecision, CodexApprovalRequest, CodexDoctor, CodexServerRequest, CodexUserInputQuestion},
+};
 
-use super::types::{BotState, TelegramStatus};
+use super::{
+  access,
+  longform::{self, Document},
+  markdown,
+  types::{
+    AccessRequestStatus,
+    BotState,
+    ChatPrefs,
+    TelegramAccessRequest,
+    TelegramPairingCode,
+    TelegramStatus,
+    TelegramWebhookInfo,
+    TurnLink,
+  },
+  webhook,
+};
 
-const NO_ACCESS_MSG: &str = "Нема доступу. Використай /whoami і додай chat_id в allowlist.";
+// Edit mode: minimum pause between edits, and where a live message rolls over to a new one.
+const LIVE_EDIT_INTERVAL: Duration = Duration::from_millis(1500);
+const LIVE_MAX_CHARS: usize = 3800;
+
+// Pairing: how long a code lives and how many wrong guesses burn it.
+const PAIRING_TTL: Duration = Duration::from_secs(10 * 60);
+const PAIRING_MAX_FAILURES: u32 = 5;
+const PAIRING_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+// Access-request inbox: at most this many chats (the least recently seen go first), and repeat
+// messages are written to disk at most this often.
+const ACCESS_REQUESTS_MAX: usize = 200;
+const ACCESS_REQUESTS_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+// How long /login waits for the sign-in, and how many failed prompts per chat are kept for retry.
+const LOGIN_WAIT: Duration = Duration::from_secs(10 * 60);
+const SIGN_IN_RETRY_MAX: usize = 5;
+
+// /logs: default and max entry count, and the size above which the output goes as a file.
+const LOGS_DEFAULT_COUNT: usize = 50;
+const LOGS_MAX_COUNT: usize = 1000;
+const LOGS_INLINE_MAX_CHARS: usize = 3500;
+
+// Replies and edits: how much of the replied-to message goes into the prompt, and how many prompt
+// and answer messages per chat are remembered for linking them back to their turn.
+const REPLY_CONTEXT_MAX_CHARS: usize = 1500;
+const TURN_LINKS_PER_CHAT: usize = 300;
+
+// Series replies: max chars per message. Long answers: summary length and the Markdown file name.
+const SERIES_MAX_CHARS: usize = 900;
+const SUMMARY_MAX_CHARS: usize = 700;
+const ANSWER_FILE_NAME: &str = "answer.md";
+
+// Update kinds the bot subscribes to (getUpdates and setWebhook).
+const ALLOWED_UPDATES: &[&str] = &["message", "edited_message", "callback_query"];
+
+const NO_ACCESS_MSG: &str = "Нема доступу. Попроси його: /request_access <коментар>";
 
 #[derive(Clone)]
 pub struct TelegramRuntime {
@@ -23,6 +103,122 @@ struct Inner {
   config: Arc<RwLock<AppConfig>>,
   codex: CodexRuntime,
   logs: logbus::LogBus,
+  // Codex questions being answered in a chat, one question at a time.
+  input_sessions: Mutex<HashMap<ChatKey, UserInputSession>>,
+  // Per-chat reply state: whether a Codex reply is running and what is waiting after it.
+  queues: Mutex<HashMap<ChatKey, ChatQueue>>,
+  // The /threads picker shown in each chat; its current page also backs `/thread <n>`.
+  thread_pickers: Mutex<HashMap<ChatKey, ThreadPicker>>,
+  // Set on start(); handlers need it for app data paths.
+  app: RwLock<Option<AppHandle>>,
+  chat_prefs: Mutex<HashMap<ChatKey, ChatPrefs>>,
+  // Bot messages that carry Codex answers, per chat, oldest first.
+  answer_links: Mutex<HashMap<ChatKey, Vec<TurnLink>>>,
+  // Prompt messages that started a turn, per chat, oldest first. Kept in memory only: they back
+  // re-runs after an edit, which only make sense for recent messages.
+  prompt_links: Mutex<HashMap<ChatKey, Vec<TurnLink>>>,
+  // Edited prompts waiting for the "re-run" button, by (chat, message). Only the chat's latest turn
+  // can be rolled back, so a chat keeps one offer, dropped when its next turn starts.
+  pending_reruns: Mutex<HashMap<(ChatKey, i64), String>>,
+  // Prompts that failed because Codex had no signed-in account; offered for retry after /login.
+  awaiting_sign_in: Mutex<HashMap<ChatKey, Vec<QueuedPrompt>>>,
+  // The active /pair code, if the desktop app started pairing.
+  pairing: Mutex<Option<Pairing>>,
+  // telegram-access-requests.json, loaded on first use.
+  access_inbox: Mutex<Option<AccessInbox>>,
+  // Set while the bot runs, for messages initiated from the desktop app.
+  bot: RwLock<Option<TgBot>>,
+}
+
+struct AccessInbox {
+  requests: Vec<TelegramAccessRequest>,
+  saved_at_unix_ms: u128,
+  // Counters changed since the last write.
+  dirty: bool,
+}
+
+impl AccessInbox {
+  fn load(app: &AppHandle) -> Result<Self, String> {
+    Ok(Self {
+      requests: load_access_requests(app)?,
+      saved_at_unix_ms: time::now_unix_ms(),
+      dirty: false,
+    })
+  }
+
+  // `force` for new entries and status changes; counter bumps wait for the save interval.
+  fn save(&mut self, app: &AppHandle, force: bool) -> Result<(), String> {
+    let now = time::now_unix_ms();
+    if !force && now < self.saved_at_unix_ms + ACCESS_REQUESTS_SAVE_INTERVAL.as_millis() {
+      self.dirty = true;
+      return Ok(());
+    }
+    save_access_requests(app, &self.requests)?;
+    self.saved_at_unix_ms = now;
+    self.dirty = false;
+    Ok(())
+  }
+
+  fn find(&mut self, chat_id: i64, user_id: Option<i64>) -> Option<&mut TelegramAccessRequest> {
+    self.requests.iter_mut().find(|r| r.chat_id == chat_id && r.user_id == user_id)
+  }
+}
+
+struct Pairing {
+  code: String,
+  expires_at_unix_ms: u128,
+  failures: u32,
+}
+
+#[derive(Default)]
+struct ChatQueue {
+  running: bool,
+  // Message of the prompt being answered right now.
+  current: Option<i64>,
+  items: VecDeque<QueuedPrompt>,
+}
+
+struct QueuedPrompt {
+  message_id: i64,
+  prompt: String,
+  // Downloaded photos, passed to Codex as `localImage` inputs.
+  images: Vec<String>,
+  // Turn to drop from the thread before this one starts (re-run of an edited prompt).
+  rollback_turn: Option<String>,
+}
+
+impl QueuedPrompt {
+  fn new(message_id: i64, prompt: String, images: Vec<String>) -> Self {
+    Self {
+      message_id,
+      prompt,
+      images,
+      rollback_turn: None,
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ThreadFilter {
+  Active,
+  Archived,
+  All,
+}
+
+struct ThreadPicker {
+  filter: ThreadFilter,
+  // Cursor of every page visited so far (None = first page); the last one is on screen.
+  cursors: Vec<Option<String>>,
+  next_cursor: Option<String>,
+  // (thread id, title) on the current page.
+  threads: Vec<(String, String)>,
+}
+
+struct UserInputSession {
+  request_id: u64,
+  questions: Vec<CodexUserInputQuestion>,
+  idx: usize,
+  answers: HashMap<String, Vec<String>>,
 }
 
 impl TelegramRuntime {
@@ -34,6 +230,18 @@ impl TelegramRuntime {
         config,
         codex,
         logs,
+        input_sessions: Mutex::new(HashMap::new()),
+        queues: Mutex::new(HashMap::new()),
+        thread_pickers: Mutex::new(HashMap::new()),
+        app: RwLock::new(None),
+        chat_prefs: Mutex::new(HashMap::new()),
+        answer_links: Mutex::new(HashMap::new()),
+        prompt_links: Mutex::new(HashMap::new()),
+        pending_reruns: Mutex::new(HashMap::new()),
+        awaiting_sign_in: Mutex::new(HashMap::new()),
+        pairing: Mutex::new(None),
+        access_inbox: Mutex::new(None),
+        bot: RwLock::new(None),
       }),
     }
   }
@@ -53,8 +261,20 @@ impl TelegramRuntime {
     if token.trim().is_empty() {
       return Err("Telegram token missing".to_string());
     }
+    let bot = TgBot::new(token, &cfg0.telegram);
+    *self.inner.bot.write().await = Some(bot.clone());
+    *self.inner.app.write().await = Some(app.clone());
+    match load_chat_prefs(&app) {
+      Ok(prefs) => *self.inner.chat_prefs.lock().await = prefs,
+      Err(e) => self.inner.logs.push(logbus::LogLevel::Warn, "telegram", format!("chat prefs: {e}")),
+    }
+    match load_answer_links(&app) {
+      Ok(links) => *self.inner.answer_links.lock().await = links,
+      Err(e) => self.inner.logs.push(logbus::LogLevel::Warn, "telegram", format!("answer links: {e}")),
+    }
 
-    self.inner.logs.push(logbus::LogLevel::Info, "telegram", "start polling");
+    let mode_label = if cfg0.telegram.mode == TelegramMode::Webhook { "webhook" } else { "polling" };
+    self.inner.logs.push(logbus::LogLevel::Info, "telegram", format!("start {mode_label}"));
     let (tx, mut rx) = watch::channel(false);
     *self.inner.stop_tx.write().await = Some(tx);
 
@@ -74,7 +294,7 @@ impl TelegramRuntime {
         .expect("reqwest client");
 
       // get bot username
-      match tg_get_me(&client, &token).await {
+      match tg_get_me(&client, &bot).await {
         Ok(username) => {
           let mut st = runtime.inner.status.write().await;
           st.bot_username = username;
@@ -90,6 +310,20 @@ impl TelegramRuntime {
         }
       }
 
+      if cfg0.telegram.mode == TelegramMode::Webhook {
+        run_webhook(&runtime, &client, &bot, &cfg0, rx).await;
+        runtime.mark_stopped().await;
+        return;
+      }
+
+      // getUpdates is rejected while a webhook is registered (e.g. after switching modes).
+      if let Err(e) = tg_delete_webhook(&client, &bot).await {
+        runtime
+          .inner
+          .logs
+          .push(logbus::LogLevel::Warn, "telegram", format!("deleteWebhook failed: {e}"));
+      }
+
       let mut state = match load_bot_state(&app) {
         Ok(s) => s,
         Err(e) => {
@@ -99,9 +333,6 @@ impl TelegramRuntime {
         }
       };
 
-      // Cache of the most recent /threads results per chat, so user can select by number (/thread 3).
-      let mut last_threads: HashMap<i64, Vec<String>> = HashMap::new();
-
       loop {
         if *rx.borrow() {
           break;
@@ -110,7 +341,7 @@ impl TelegramRuntime {
         let cfg = runtime.inner.config.read().await.clone();
         let poll_timeout = cfg.telegram.poll_timeout_sec.clamp(1, 60) as i64;
 
-        let updates_fut = tg_get_updates(&client, &token, state.offset, poll_timeout);
+        let updates_fut = tg_get_updates(&client, &bot, state.offset, poll_timeout);
         let updates = tokio::select! {
           _ = rx.changed() => break,
           res = updates_fut => res,
@@ -130,186 +361,7 @@ impl TelegramRuntime {
 
             // process messages
             for msg in items {
-              if let Some((chat_id, message_id, text)) = extract_text_message(&msg) {
-                let trimmed = text.trim();
-                if trimmed.is_empty() {
-                  continue;
-                }
-
-                let (cmd, rest) = parse_command(trimmed);
-                runtime
-                  .inner
-                  .logs
-                  .push(logbus::LogLevel::Info, "telegram", format!("msg chat_id={chat_id} cmd={}", cmd.clone().unwrap_or_else(|| "(text)".to_string())));
-                match cmd.as_deref() {
-                  Some("/start") => {
-                    let body = "Бот підключено.\n\nКоманди:\n/whoami\n/ping";
-                    if let Err(e) = tg_send_message(&client, &token, chat_id, body, Some(message_id)).await {
-                      log::info!("telegram: send /start reply failed: {e}");
-                    }
-                  }
-                  Some("/whoami") => {
-                    let body = format!("chat_id: {chat_id}");
-                    if let Err(e) = tg_send_message(&client, &token, chat_id, &body, Some(message_id)).await {
-                      log::info!("telegram: send /whoami reply failed: {e}");
-                    }
-                  }
-                  Some("/ping") => {
-                    let allowed = cfg.telegram.allowed_chat_ids.contains(&chat_id);
-                    if allowed {
-                      if let Err(e) = tg_send_message(&client, &token, chat_id, "pong", Some(message_id)).await {
-                        log::info!("telegram: send /ping reply failed: {e}");
-                      }
-                    } else if let Err(e) = tg_send_message(&client, &token, chat_id, NO_ACCESS_MSG, Some(message_id)).await {
-                      log::info!("telegram: send /ping deny failed: {e}");
-                    }
-                  }
-                  Some("/codex") => {
-                    let allowed = cfg.telegram.allowed_chat_ids.contains(&chat_id);
-                    if !allowed {
-                      if let Err(e) = tg_send_message(&client, &token, chat_id, NO_ACCESS_MSG, Some(message_id))
-                      .await
-                      {
-                        log::info!("telegram: send /codex deny failed: {e}");
-                      }
-                      continue;
-                    }
-                    let prompt = match rest {
-                      Some(p) if !p.trim().is_empty() => p.trim().to_string(),
-                      _ => {
-                        if let Err(e) =
-                          tg_send_message(&client, &token, chat_id, "Напиши: /codex <повідомлення>", Some(message_id))
-                            .await
-                        {
-                          log::info!("telegram: send /codex help failed: {e}");
-                        }
-                        continue;
-                      }
-                    };
-
-                    runtime.inner.logs.push(logbus::LogLevel::Info, "telegram", format!("codex request chat_id={chat_id}"));
-                    spawn_codex_reply(
-                      client.clone(),
-                      token.clone(),
-                      runtime.inner.codex.clone(),
-                      runtime.inner.logs.clone(),
-                      chat_id,
-                      message_id,
-                      prompt,
-                    );
-                  }
-                  Some("/threads") => {
-                    let allowed = cfg.telegram.allowed_chat_ids.contains(&chat_id);
-                    if !allowed {
-                      if let Err(e) = tg_send_message(&client, &token, chat_id, NO_ACCESS_MSG, Some(message_id)).await {
-                        log::info!("telegram: send /threads deny failed: {e}");
-                      }
-                      continue;
-                    }
-
-                    let res = runtime.inner.codex.list_threads(20, None).await;
-                    let body = match res {
-                      Ok(r) => {
-                        if r.threads.is_empty() {
-                          "Поки що немає діалогів.".to_string()
-                        } else {
-                          last_threads.insert(chat_id, r.threads.iter().take(10).map(|t| t.id.clone()).collect());
-                          let mut out = String::new();
-                          out.push_str("Останні діалоги:\n");
-                          for (i, th) in r.threads.iter().take(10).enumerate() {
-                            let title = th
-                              .title
-                              .clone()
-                              .or_else(|| th.preview.clone())
-                              .unwrap_or_else(|| "Діалог".to_string());
-                            out.push_str(&format!("\n{}. {title}", i + 1));
-                          }
-                          out.push_str("\n\nПродовжити: /thread <номер> (наприклад /thread 1)");
-                          out.trim().to_string()
-                        }
-                      }
-                      Err(e) => format!("Codex error: {e}"),
-                    };
-                    if let Err(e) = tg_send_message_series(&client, &token, chat_id, &body, Some(message_id)).await {
-                      log::info!("telegram: send /threads reply failed: {e}");
-                    }
-                  }
-                  Some("/thread") => {
-                    let allowed = cfg.telegram.allowed_chat_ids.contains(&chat_id);
-                    if !allowed {
-                      if let Err(e) = tg_send_message(&client, &token, chat_id, NO_ACCESS_MSG, Some(message_id)).await {
-                        log::info!("telegram: send /thread deny failed: {e}");
-                      }
-                      continue;
-                    }
-
-                    let rest = rest.clone().unwrap_or_default();
-                    if rest.trim().is_empty() {
-                      let cur = runtime.inner.codex.get_chat_thread(chat_id).await;
-                      let body = match cur {
-                        Some(id) => format!("Поточний діалог:\n{id}\n\nЗмінити: /thread <id>\nСписок: /threads"),
-                        None => "Немає вибраного діалогу.\n\nВибрати: /thread <id>\nСписок: /threads".to_string(),
-                      };
-                      if let Err(e) = tg_send_message_series(&client, &token, chat_id, &body, Some(message_id)).await {
-                        log::info!("telegram: send /thread help failed: {e}");
-                      }
-                      continue;
-                    }
-
-                    let raw = rest.trim().to_string();
-                    let thread_id = if let Ok(n) = raw.parse::<usize>() {
-                      last_threads
-                        .get(&chat_id)
-                        .and_then(|v| v.get(n.saturating_sub(1)))
-                        .cloned()
-                        .unwrap_or_default()
-                    } else {
-                      raw
-                    };
-                    if thread_id.trim().is_empty() {
-                      let msg = "Невірний номер. Спочатку виклич /threads і вибери 1-10.".to_string();
-                      if let Err(e) = tg_send_message_series(&client, &token, chat_id, &msg, Some(message_id)).await {
-                        log::info!("telegram: send /thread invalid failed: {e}");
-                      }
-                      continue;
-                    }
-
-                    // Attach this Telegram chat to a specific Codex thread id.
-                    match runtime.inner.codex.attach_chat_to_thread(chat_id, thread_id).await {
-                      Ok(_) => {
-                        if let Err(e) = tg_send_message(&client, &token, chat_id, "OK. Підключив до вибраного діалогу.", Some(message_id)).await {
-                          log::info!("telegram: send /thread ok failed: {e}");
-                        }
-                      }
-                      Err(e) => {
-                        let msg = format!("Codex error: {e}");
-                        if let Err(e) = tg_send_message_series(&client, &token, chat_id, &msg, Some(message_id)).await {
-                          log::info!("telegram: send /thread err failed: {e}");
-                        }
-                      }
-                    }
-                  }
-                  _ => {}
-                }
-
-                // For allowlisted chats: treat any non-command message as Codex input.
-                if cmd.is_none() {
-                  let allowed = cfg.telegram.allowed_chat_ids.contains(&chat_id);
-                  if allowed {
-                    let prompt = trimmed.to_string();
-                    runtime.inner.logs.push(logbus::LogLevel::Info, "telegram", format!("codex request chat_id={chat_id}"));
-                    spawn_codex_reply(
-                      client.clone(),
-                      token.clone(),
-                      runtime.inner.codex.clone(),
-                      runtime.inner.logs.clone(),
-                      chat_id,
-                      message_id,
-                      prompt,
-                    );
-                  }
-                }
-              }
+              handle_update(&runtime, &client, &bot, msg).await;
             }
 
             state.offset = new_offset;
@@ -332,13 +384,108 @@ impl TelegramRuntime {
         }
       }
 
-      // stopped
-      *runtime.inner.stop_tx.write().await = None;
-      let mut st = runtime.inner.status.write().await;
-      st.running = false;
-      runtime.inner.logs.push(logbus::LogLevel::Info, "telegram", "stopped");
+      runtime.mark_stopped().await;
+    });
+
+    Ok(())
+  }
+
+  async fn mark_stopped(&self) {
+    *self.inner.stop_tx.write().await = None;
+    *self.inner.bot.write().await = None;
+    let mut st = self.inner.status.write().await;
+    st.running = false;
+    self.inner.logs.push(logbus::LogLevel::Info, "telegram", "stopped");
+  }
+
+  // New one-time code for `/pair`; replaces any previous one.
+  pub async fn start_pairing(&self) -> Result<TelegramPairingCode, String> {
+    let code = new_pairing_code()?;
+    let expires_at_unix_ms = time::now_unix_ms() + PAIRING_TTL.as_millis();
+    *self.inner.pairing.lock().await = Some(Pairing {
+      code: code.clone(),
+      expires_at_unix_ms,
+      failures: 0,
     });
+    self.inner.logs.push(logbus::LogLevel::Info, "telegram", "pairing code issued");
+    Ok(TelegramPairingCode { code, expires_at_unix_ms })
+  }
+
+  pub async fn cancel_pairing(&self) {
+    if self.inner.pairing.lock().await.take().is_some() {
+      self.inner.logs.push(logbus::LogLevel::Info, "telegram", "pairing cancelled");
+    }
+  }
+
+  // Chats that tried to use the bot without access, most recent first.
+  pub async fn access_requests(&self, app: &AppHandle) -> Result<Vec<TelegramAccessRequest>, String> {
+    let mut list: Vec<TelegramAccessRequest> =
+      with_access_inbox(self, app, |inbox| inbox.requests.clone()).await?;
+    list.sort_by_key(|r| std::cmp::Reverse(r.last_seen_unix_ms));
+    Ok(list)
+  }
+
+  // Private chat: grants the chat (like /pair). Group: grants the requester the user role in that
+  // group only, not everyone in it. Drops the request and tells the chat.
+  pub async fn approve_access_request(&self, app: &AppHandle, chat_id: i64, user_id: Option<i64>) -> Result<(), String> {
+    let req = with_access_inbox(self, app, |inbox| inbox.find(chat_id, user_id).cloned())
+      .await?
+      .ok_or_else(|| format!("No access request for chat {chat_id}"))?;
+    let body = if req.chat_title.is_none() {
+      grant_chat_access(self, app, chat_id).await?;
+      "Доступ надано. Пиши повідомлення — я передам їх Codex.".to_string()
+    } else {
+      let user_id = user_id.ok_or_else(|| format!("Access request for chat {chat_id} has no sender"))?;
+      update_access(self, app, chat_id, Some(user_id), |tg| {
+        if !tg.access.iter().any(|r| r.user_id == Some(user_id) && r.chat_id == Some(chat_id)) {
+          tg.access.push(AccessRule {
+            user_id: Some(user_id),
+            chat_id: Some(chat_id),
+            role: Role::User,
+          });
+        }
+      })
+      .await?;
+      let who = if req.name.is_empty() { user_id.to_string() } else { req.name.clone() };
+      format!("Доступ надано: {who} може писати повідомлення — я передам їх Codex.")
+    };
+    self.inner.logs.push(
+      logbus::LogLevel::Info,
+      "telegram",
+      format!("access request approved chat_id={chat_id} user_id={}", req.user_id.unwrap_or(0)),
+    );
+
+    let Some(bot) = self.inner.bot.read().await.clone() else {
+      self
+        .inner
+        .logs
+        .push(logbus::LogLevel::Warn, "telegram", "access approved while the bot is stopped; chat not notified");
+      return Ok(());
+    };
+    if let Err(e) = tg_send_message(&Client::new(), &bot, chat_id.into(), &body, None).await {
+      self
+        .inner
+        .logs
+        .push(logbus::LogLevel::Warn, "telegram", format!("access approval notice failed chat_id={chat_id}: {e}"));
+    }
+    Ok(())
+  }
 
+  // Keeps the entry (so repeated attempts still show up) but marks it denied.
+  pub async fn deny_access_request(&self, app: &AppHandle, chat_id: i64, user_id: Option<i64>) -> Result<(), String> {
+    with_access_inbox(self, app, |inbox| {
+      let req = inbox
+        .find(chat_id, user_id)
+        .ok_or_else(|| format!("No access request for chat {chat_id}"))?;
+      req.status = AccessRequestStatus::Denied;
+      inbox.save(app, true)
+    })
+    .await??;
+    self.inner.logs.push(
+      logbus::LogLevel::Info,
+      "telegram",
+      format!("access request denied chat_id={chat_id} user_id={}", user_id.unwrap_or(0)),
+    );
     Ok(())
   }
 
@@ -346,264 +493,3048 @@ impl TelegramRuntime {
     if let Some(tx) = self.inner.stop_tx.read().await.clone() {
       let _ = tx.send(true);
     }
+    // Write counters still held back by the save interval.
+    if let Some(app) = self.inner.app.read().await.clone() {
+      if let Some(inbox) = self.inner.access_inbox.lock().await.as_mut().filter(|i| i.dirty) {
+        inbox.save(&app, true)?;
+      }
+    }
     Ok(())
   }
 }
 
-fn spawn_codex_reply(
-  client: Client,
-  token: String,
-  codex: CodexRuntime,
-  logs: logbus::LogBus,
-  chat_id: i64,
-  message_id: i64,
-  prompt: String,
+// Webhook mode: registers the webhook, feeds incoming updates to the dispatcher until stopped,
+// then unregisters it so polling works again.
+async fn run_webhook(
+  runtime: &TelegramRuntime,
+  client: &Client,
+  bot: &TgBot,
+  cfg: &AppConfig,
+  mut stop_rx: watch::Receiver<bool>,
 ) {
-  tauri::async_runtime::spawn(async move {
-    let (typing_tx, mut typing_rx) = watch::channel(false);
+  let url = cfg.telegram.webhook_url.clone().unwrap_or_default().trim().to_string();
+  let fail = |e: String| async move {
+    runtime.inner.logs.push(logbus::LogLevel::Error, "telegram", format!("webhook: {e}"));
+    runtime.inner.status.write().await.last_error = Some(e);
+  };
+  if url.is_empty() {
+    fail("webhook URL is not set".to_string()).await;
+    return;
+  }
 
-    // Standard Telegram loader while Codex works.
-    let client3 = client.clone();
-    let token3 = token.clone();
-    let logs3 = logs.clone();
-    tauri::async_runtime::spawn(async move {
-      loop {
-        if *typing_rx.borrow() {
-          break;
+  let listener = match webhook::bind(&cfg.telegram.webhook_listen).await {
+    Ok(l) => l,
+    Err(e) => {
+      fail(e).await;
+      return;
+    }
+  };
+  let secret = match webhook::random_secret() {
+    Ok(s) => s,
+    Err(e) => {
+      fail(e).await;
+      return;
+    }
+  };
+  let (tx, mut rx) = mpsc::unbounded_channel::<serde_json::Value>();
+  tauri::async_runtime::spawn(webhook::serve(listener, secret.clone(), tx, runtime.inner.logs.clone()));
+
+  if let Err(e) = tg_set_webhook(client, bot, &url, &secret).await {
+    fail(format!("setWebhook failed: {e}")).await;
+    return;
+  }
+  runtime.inner.logs.push(
+    logbus::LogLevel::Info,
+    "telegram",
+    format!("webhook registered, listening on {}", cfg.telegram.webhook_listen.trim()),
+  );
+
+  loop {
+    let raw = tokio::select! {
+      _ = stop_rx.changed() => break,
+      maybe = rx.recv() => match maybe {
+        Some(v) => v,
+        None => break,
+      },
+    };
+    {
+      let mut st = runtime.inner.status.write().await;
+      st.last_poll_unix_ms = Some(time::now_unix_ms());
+      st.last_error = None;
+    }
+    match serde_json::from_value::<TgUpdate>(raw) {
+      Ok(update) => handle_update(runtime, client, bot, update).await,
+      Err(e) => runtime
+        .inner
+        .logs
+        .push(logbus::LogLevel::Warn, "telegram", format!("webhook update parse failed: {e}")),
+    }
+  }
+
+  if let Err(e) = tg_delete_webhook(client, bot).await {
+    runtime
+      .inner
+      .logs
+      .push(logbus::LogLevel::Warn, "telegram", format!("deleteWebhook failed: {e}"));
+  }
+}
+
+// Handles one update from either transport (long polling or webhook).
+async fn handle_update(runtime: &TelegramRuntime, client: &Client, bot: &TgBot, mut msg: TgUpdate) {
+  let cfg = runtime.inner.config.read().await.clone();
+  if let Some(cb) = msg.callback_query.as_ref() {
+    handle_callback_query(runtime, client, bot, &cfg, cb).await;
+    return;
+  }
+  // Edits go through the same group filter and access checks as new messages.
+  let edited = msg.message.is_none() && msg.edited_message.is_some();
+  if edited {
+    msg.message = msg.edited_message.take();
+  }
+  if let Some(m) = msg.message.as_mut().filter(|m| m.chat.is_group()) {
+    let username = runtime.inner.status.read().await.bot_username.clone();
+    if !addressed_to_bot(m, username.as_deref()) {
+      return;
+    }
+    if let Some(name) = username.as_deref() {
+      strip_bot_mention(m, name);
+    }
+  }
+  if let Some(m) = msg.message.as_ref() {
+    if !authorize_message(runtime, client, bot, &cfg, m).await {
+      return;
+    }
+  }
+  if let Some(m) = msg.message.as_ref().filter(|_| edited) {
+    handle_edited_message(runtime, client, bot, m).await;
+    return;
+  }
+  if let Some(m) = msg.message.as_ref() {
+    follow_reply_thread(runtime, client, bot, &cfg, m).await;
+  }
+  if let Some(m) = msg.message.as_ref().filter(|m| m.voice.is_some() || m.audio.is_some()) {
+    handle_voice_message(runtime, client, bot, &cfg, m).await;
+    return;
+  }
+  if let Some(m) = msg.message.as_ref().filter(|m| m.photo.is_some() || m.document.is_some()) {
+    handle_attachment_message(runtime, client, bot, &cfg, m).await;
+    return;
+  }
+  if let Some((chat, message_id, text)) = extract_text_message(&msg) {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+      return;
+    }
+
+    let (cmd, rest) = parse_command(trimmed);
+    runtime
+      .inner
+      .logs
+      .push(logbus::LogLevel::Info, "telegram", format!("msg chat_id={chat} cmd={}", cmd.clone().unwrap_or_else(|| "(text)".to_string())));
+    match cmd.as_deref() {
+      Some("/start") => {
+        let body = "Бот підключено.\n\nКоманди:\n/whoami\n/ping\n/pair <код> — підключити цей чат кодом із застосунку";
+        if let Err(e) = tg_send_message(client, bot, chat, body, Some(message_id)).await {
+          log::info!("telegram: send /start reply failed: {e}");
         }
-        if let Err(e) = tg_send_chat_action(&client3, &token3, chat_id, "typing").await {
-          logs3.push(logbus::LogLevel::Warn, "telegram", format!("sendChatAction failed: {e}"));
-          break;
+      }
+      Some("/whoami") => {
+        let user_id = msg.message.as_ref().and_then(|m| m.from.as_ref()).map(|u| u.id);
+        let role = access::role_for(&cfg.telegram, user_id, chat.chat_id);
+        let mut body = format!("chat_id: {}\n", chat.chat_id);
+        if let Some(topic) = chat.topic_id {
+          body.push_str(&format!("topic_id: {topic}\n"));
         }
-        tokio::select! {
-          _ = typing_rx.changed() => break,
-          _ = tokio::time::sleep(Duration::from_secs(4)) => {}
+        body.push_str(&format!(
+          "user_id: {}\nроль: {}",
+          user_id.map(|u| u.to_string()).unwrap_or_else(|| "-".to_string()),
+          role.map(access::role_label).unwrap_or("немає доступу")
+        ));
+        if let Err(e) = tg_send_message(client, bot, chat, &body, Some(message_id)).await {
+          log::info!("telegram: send /whoami reply failed: {e}");
         }
       }
-    });
-
-    let mut stream = match codex.start_turn_stream(chat_id, &prompt).await {
-      Ok(s) => s,
-      Err(e) => {
-        let _ = typing_tx.send(true);
-        let msg = if e == "Busy" {
-          "Зачекай: обробляю попереднє повідомлення.".to_string()
+      Some("/pair") => {
+        let body = pair_chat(runtime, &cfg, chat.chat_id, rest.as_deref().unwrap_or("")).await;
+        if let Err(e) = tg_send_message(client, bot, chat, &body, Some(message_id)).await {
+          log::info!("telegram: send /pair reply failed: {e}");
+        }
+      }
+      Some("/request_access") => {
+        let user_id = msg.message.as_ref().and_then(|m| m.from.as_ref()).map(|u| u.id);
+        let body = if access::role_for(&cfg.telegram, user_id, chat.chat_id).is_some() {
+          "У тебе вже є доступ.".to_string()
+        } else if let Some(m) = msg.message.as_ref() {
+          match record_access_request(runtime, m, rest.clone()).await {
+            Ok(()) => {
+              runtime
+                .inner
+                .logs
+                .push(logbus::LogLevel::Info, "telegram", format!("access requested chat_id={chat}"));
+              "Запит надіслано. Власник бота побачить його в застосунку.".to_string()
+            }
+            Err(e) => format!("Не вдалося надіслати запит: {e}"),
+          }
         } else {
-          format!("Codex error: {e}")
+          return;
         };
-        if let Err(e) = tg_send_message_series(&client, &token, chat_id, &msg, Some(message_id)).await {
-          logs.push(logbus::LogLevel::Warn, "telegram", format!("sendMessageSeries failed: {e}"));
+        if let Err(e) = tg_send_message(client, bot, chat, &body, Some(message_id)).await {
+          log::info!("telegram: send /request_access reply failed: {e}");
         }
-        return;
       }
-    };
-
-    logs.push(logbus::LogLevel::Info, "telegram", format!("codex stream started chat_id={chat_id}"));
+      Some("/login") => {
+        start_remote_login(runtime, client, bot, chat, message_id).await;
+      }
+      Some(c @ ("/status" | "/doctor" | "/restart" | "/logs")) => {
+        admin_command(runtime, client, bot, chat, message_id, c, rest.as_deref()).await;
+      }
+      Some("/ping") => {
+        if let Err(e) = tg_send_message(client, bot, chat, "pong", Some(message_id)).await {
+          log::info!("telegram: send /ping reply failed: {e}");
+        }
+      }
+      Some("/codex") => {
+        let prompt = match rest {
+          Some(p) if !p.trim().is_empty() => p.trim().to_string(),
+          _ => {
+            if let Err(e) =
+              tg_send_message(client, bot, chat, "Напиши: /codex <повідомлення>", Some(message_id))
+                .await
+            {
+              log::info!("telegram: send /codex help failed: {e}");
+            }
+            return;
+          }
+        };
 
-    let mut first_reply = true;
-    let mut sent_any = false;
-    let mut updates_closed = false;
-    let mut first_chunk_logged = false;
-    let mut done_rx = stream.done_rx;
-    loop {
-      tokio::select! {
-        maybe = stream.updates_rx.recv(), if !updates_closed => {
-          let Some(chunk) = maybe else {
-            updates_closed = true;
-            logs.push(logbus::LogLevel::Warn, "telegram", format!("codex updates channel closed chat_id={chat_id}"));
-            continue;
+        let prompt = match msg.message.as_ref() {
+          Some(m) => compose_prompt(runtime, m, &prompt).await,
+          None => prompt,
+        };
+        runtime.inner.logs.push(logbus::LogLevel::Info, "telegram", format!("codex request chat_id={chat}"));
+        submit_codex_prompt(runtime, client, bot, &cfg, chat, QueuedPrompt::new(message_id, prompt, vec![])).await;
+      }
+      Some("/stop") => {
+        let body = stop_codex_turn(runtime, chat).await;
+        if let Err(e) = tg_send_message(client, bot, chat, body, Some(message_id)).await {
+          log::info!("telegram: send /stop reply failed: {e}");
+        }
+      }
+      Some(c @ ("/new" | "/rename" | "/archive" | "/unarchive" | "/fork")) => {
+        let body = thread_command(runtime, chat, c, rest.as_deref()).await;
+        if let Err(e) = tg_send_message(client, bot, chat, &body, Some(message_id)).await {
+          log::info!("telegram: send {c} reply failed: {e}");
+        }
+      }
+      Some("/stream") => {
+        if let Some(arg) = rest.as_deref().map(|r| r.trim().to_ascii_lowercase()) {
+          let body = match parse_stream_mode(&arg) {
+            Some(mode) => match set_chat_stream_mode(runtime, chat, mode).await {
+              Ok(()) => format!("Режим відповіді: {}", stream_mode_label(mode)),
+              Err(e) => format!("Не вдалося зберегти: {e}"),
+            },
+            None => "Невідомий режим. Доступні: chunks, edit, final.".to_string(),
           };
-          let reply_to = if first_reply { Some(message_id) } else { None };
-          first_reply = false;
-          sent_any = true;
-          if !first_chunk_logged {
-            first_chunk_logged = true;
-            logs.push(logbus::LogLevel::Info, "telegram", format!("codex first chunk chat_id={chat_id}"));
-          }
-          if let Err(e) = tg_send_message(&client, &token, chat_id, &chunk, reply_to).await {
-            logs.push(logbus::LogLevel::Warn, "telegram", format!("sendMessage failed: {e}"));
-          }
-        }
-        done = &mut done_rx => {
-          // Stop typing loader.
-          let _ = typing_tx.send(true);
-          // Drain any chunks that were queued before completion.
-          while let Ok(chunk) = stream.updates_rx.try_recv() {
-            let reply_to = if first_reply { Some(message_id) } else { None };
-            first_reply = false;
-            sent_any = true;
-            if let Err(e) = tg_send_message(&client, &token, chat_id, &chunk, reply_to).await {
-              logs.push(logbus::LogLevel::Warn, "telegram", format!("sendMessage failed: {e}"));
-            }
+          if let Err(e) = tg_send_message(client, bot, chat, &body, Some(message_id)).await {
+            log::info!("telegram: send /stream reply failed: {e}");
+          }
+          return;
+        }
+        let mode = chat_stream_mode(runtime, &cfg, chat).await;
+        let body = format!("Режим відповіді: {}", stream_mode_label(mode));
+        if let Err(e) = tg_send_message_markup(client, bot, chat, &body, Some(message_id), stream_mode_markup()).await {
+          log::info!("telegram: send /stream menu failed: {e}");
+        }
+      }
+      Some("/workspace") => {
+        if let Some(arg) = rest.as_deref().map(str::trim).filter(|r| !r.is_empty()) {
+          let body = bind_workspace(runtime, chat, arg).await;
+          if let Err(e) = tg_send_message(client, bot, chat, &body, Some(message_id)).await {
+            log::info!("telegram: send /workspace reply failed: {e}");
+          }
+          return;
+        }
+        let (body, markup) = workspace_menu(runtime, chat).await;
+        let res = match markup {
+          Some(markup) => tg_send_message_markup(client, bot, chat, &body, Some(message_id), markup).await.map(|_| ()),
+          None => tg_send_message(client, bot, chat, &body, Some(message_id)).await,
+        };
+        if let Err(e) = res {
+          log::info!("telegram: send /workspace menu failed: {e}");
+        }
+      }
+      Some("/settings") => {
+        let (body, markup) = settings_menu(runtime, chat).await;
+        if let Err(e) = tg_send_message_markup(client, bot, chat, &body, Some(message_id), markup).await {
+          log::info!("telegram: send /settings menu failed: {e}");
+        }
+      }
+      Some("/queue") => {
+        let body = if rest.as_deref().map(|r| r.trim()) == Some("clear") {
+          let mut queues = runtime.inner.queues.lock().await;
+          let n = queues.get_mut(&chat).map(|q| q.items.drain(..).count()).unwrap_or(0);
+          format!("Чергу очищено ({n}).")
+        } else {
+          let queues = runtime.inner.queues.lock().await;
+          queues.get(&chat).map(|q| queue_summary(&q.items)).unwrap_or_else(|| "Черга порожня.".to_string())
+        };
+        if let Err(e) = tg_send_message(client, bot, chat, &body, Some(message_id)).await {
+          log::info!("telegram: send /queue reply failed: {e}");
+        }
+      }
+      Some("/threads") => {
+        let (body, markup) = load_thread_page(runtime, chat, ThreadFilter::Active, vec![None], None).await;
+        let res = match markup {
+          Some(markup) => tg_send_message_markup(client, bot, chat, &body, Some(message_id), markup).await.map(|_| ()),
+          None => tg_send_message(client, bot, chat, &body, Some(message_id)).await,
+        };
+        if let Err(e) = res {
+          log::info!("telegram: send /threads reply failed: {e}");
+        }
+      }
+      Some("/thread") => {
+        let rest = rest.clone().unwrap_or_default();
+        if rest.trim().is_empty() {
+          let cur = runtime.inner.codex.get_chat_thread(chat).await;
+          let body = match cur {
+            Some(id) => format!("Поточний діалог:\n{id}\n\nЗмінити: /thread <id>\nСписок: /threads"),
+            None => "Немає вибраного діалогу.\n\nВибрати: /thread <id>\nСписок: /threads".to_string(),
+          };
+          if let Err(e) = tg_send_message_series(client, bot, chat, &body, Some(message_id)).await {
+            log::info!("telegram: send /thread help failed: {e}");
           }
+          return;
+        }
 
-          match done {
-            Ok(Ok(final_text)) => {
-              logs.push(logbus::LogLevel::Info, "telegram", format!("codex done ok chat_id={chat_id} chars={}", final_text.chars().count()));
-              if !sent_any {
-                if final_text.trim().is_empty() {
-                  let msg = "Нема відповіді від Codex. Спробуй ще раз.".to_string();
-                  if let Err(e) = tg_send_message(&client, &token, chat_id, &msg, Some(message_id)).await {
-                    logs.push(logbus::LogLevel::Warn, "telegram", format!("sendMessage failed: {e}"));
-                  }
-                } else if let Err(e) = tg_send_message_series(&client, &token, chat_id, &final_text, Some(message_id)).await {
-                  logs.push(logbus::LogLevel::Warn, "telegram", format!("sendMessageSeries failed: {e}"));
-                }
-              }
-            }
-            Ok(Err(e)) => {
-              logs.push(logbus::LogLevel::Warn, "telegram", format!("codex done err chat_id={chat_id}: {e}"));
-              let msg = format!("Codex error: {e}");
-              if let Err(e) = tg_send_message_series(&client, &token, chat_id, &msg, Some(message_id)).await {
-                logs.push(logbus::LogLevel::Warn, "telegram", format!("sendMessageSeries failed: {e}"));
-              }
+        let thread_id = resolve_thread_arg(runtime, chat, Some(&rest)).await.unwrap_or_default();
+        if thread_id.trim().is_empty() {
+          let msg = "Невірний номер. Спочатку виклич /threads.".to_string();
+          if let Err(e) = tg_send_message_series(client, bot, chat, &msg, Some(message_id)).await {
+            log::info!("telegram: send /thread invalid failed: {e}");
+          }
+          return;
+        }
+
+        // Attach this Telegram chat to a specific Codex thread id.
+        match runtime.inner.codex.attach_chat_to_thread(chat, thread_id).await {
+          Ok(_) => {
+            if let Err(e) = tg_send_message(client, bot, chat, "OK. Підключив до вибраного діалогу.", Some(message_id)).await {
+              log::info!("telegram: send /thread ok failed: {e}");
             }
-            Err(_) => {
-              logs.push(logbus::LogLevel::Warn, "telegram", format!("codex done channel closed chat_id={chat_id}"));
-              if let Err(e) = tg_send_message_series(&client, &token, chat_id, "Codex error: internal channel closed", Some(message_id)).await {
-                logs.push(logbus::LogLevel::Warn, "telegram", format!("sendMessageSeries failed: {e}"));
-              }
+          }
+          Err(e) => {
+            let msg = format!("Codex error: {e}");
+            if let Err(e) = tg_send_message_series(client, bot, chat, &msg, Some(message_id)).await {
+              log::info!("telegram: send /thread err failed: {e}");
             }
           }
-          break;
         }
       }
+      _ => {}
     }
-  });
-}
 
-fn redact_token(s: &str, token: &str) -> String {
-  if token.trim().is_empty() {
-    return s.to_string();
+    // Any non-command message is Codex input (access was checked above).
+    if cmd.is_none() {
+      if answer_pending_question(runtime, client, bot, chat, trimmed).await {
+        return;
+      }
+      let prompt = match msg.message.as_ref() {
+        Some(m) => compose_prompt(runtime, m, trimmed).await,
+        None => trimmed.to_string(),
+      };
+      runtime.inner.logs.push(logbus::LogLevel::Info, "telegram", format!("codex request chat_id={chat}"));
+      submit_codex_prompt(runtime, client, bot, &cfg, chat, QueuedPrompt::new(message_id, prompt, vec![])).await;
+    }
   }
-  s.replace(token, "[REDACTED]")
 }
 
-fn format_reqwest_error(e: &reqwest::Error, token: &str) -> String {
-  let mut parts: Vec<String> = vec![];
+// The alphabet has 32 chars, so a random byte mod 32 picks each one with equal odds.
+fn new_pairing_code() -> Result<String, String> {
+  let mut buf = [0u8; 8];
+  getrandom::getrandom(&mut buf).map_err(|e| format!("OS random source failed: {e}"))?;
+  Ok(buf.iter().map(|b| PAIRING_ALPHABET[*b as usize % PAIRING_ALPHABET.len()] as char).collect())
+}
 
-  let mut base = e.to_string();
-  base = redact_token(&base, token);
-  parts.push(base);
+// `/pair CODE`: checks the code issued by the desktop app and, on success, adds the chat to
+// `allowed_chat_ids` and saves the config. Failures are logged; too many of them burn the code.
+async fn pair_chat(runtime: &TelegramRuntime, cfg: &AppConfig, chat_id: i64, arg: &str) -> String {
+  if access::role_for(&cfg.telegram, None, chat_id).is_some() {
+    return "Цей чат уже підключено.".to_string();
+  }
+  let given: String = arg.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_ascii_uppercase();
+  let logs = &runtime.inner.logs;
+  let fail = |reason: &str| {
+    logs.push(logbus::LogLevel::Warn, "telegram", format!("pairing failed chat_id={chat_id}: {reason}"));
+  };
 
-  if e.is_timeout() {
-    parts.push("timeout".to_string());
+  {
+    let mut guard = runtime.inner.pairing.lock().await;
+    let Some(p) = guard.as_mut() else {
+      fail("no active code");
+      return "Немає активного коду. Згенеруй новий у застосунку.".to_string();
+    };
+    if time::now_unix_ms() > p.expires_at_unix_ms {
+      *guard = None;
+      fail("code expired");
+      return "Код прострочений. Згенеруй новий у застосунку.".to_string();
+    }
+    if given.is_empty() {
+      return "Напиши: /pair <код>".to_string();
+    }
+    if given != p.code {
+      p.failures += 1;
+      if p.failures >= PAIRING_MAX_FAILURES {
+        *guard = None;
+        fail("wrong code, code revoked after too many attempts");
+        return "Невірний код. Забагато спроб — згенеруй новий у застосунку.".to_string();
+      }
+      fail("wrong code");
+      return "Невірний код.".to_string();
+    }
+    *guard = None;
   }
-  if e.is_connect() {
-    parts.push("connect".to_string());
+
+  let saved = async {
+    let app = runtime.inner.app.read().await.clone().ok_or_else(|| "app handle missing".to_string())?;
+    grant_chat_access(runtime, &app, chat_id).await
   }
-  if let Some(status) = e.status() {
-    parts.push(format!("http {status}"));
+  .await;
+  match saved {
+    Ok(()) => {
+      logs.push(logbus::LogLevel::Info, "telegram", format!("chat paired chat_id={chat_id}"));
+      "Готово, чат підключено. Пиши повідомлення — я передам їх Codex.".to_string()
+    }
+    Err(e) => {
+      fail(&format!("save config: {e}"));
+      format!("Не вдалося зберегти налаштування: {e}")
+    }
   }
+}
 
-  // Include a short error chain (often has the real cause).
-  let mut src = e.source();
-  let mut depth = 0usize;
-  while let Some(s) = src {
-    depth += 1;
-    if depth > 4 {
-      break;
+// Adds the chat to `allowed_chat_ids` (pairing, approved requests from private chats).
+async fn grant_chat_access(runtime: &TelegramRuntime, app: &AppHandle, chat_id: i64) -> Result<(), String> {
+  update_access(runtime, app, chat_id, None, |tg| {
+    if !tg.allowed_chat_ids.contains(&chat_id) {
+      tg.allowed_chat_ids.push(chat_id);
     }
-    let mut t = s.to_string();
-    t = redact_token(&t, token);
-    parts.push(t);
-    src = s.source();
-  }
+  })
+  .await
+}
 
-  parts.join(": ")
+// Applies an access change for `chat_id` and saves the config, then drops the matching access
+// requests: only `user_id`'s when given, all of the chat's otherwise. The UI reloads its config copy
+// on `telegram://paired` so its next save keeps the change.
+async fn update_access(
+  runtime: &TelegramRuntime,
+  app: &AppHandle,
+  chat_id: i64,
+  user_id: Option<i64>,
+  change: impl FnOnce(&mut TelegramConfig),
+) -> Result<(), String> {
+  let cfg = {
+    let mut guard = runtime.inner.config.write().await;
+    change(&mut guard.telegram);
+    guard.clone()
+  };
+  config_store::save_config(&paths::config_path(app)?, &cfg)?;
+  with_access_inbox(runtime, app, |inbox| {
+    let before = inbox.requests.len();
+    inbox
+      .requests
+      .retain(|r| r.chat_id != chat_id || user_id.is_some_and(|u| r.user_id != Some(u)));
+    if inbox.requests.len() == before {
+      return Ok(());
+    }
+    inbox.save(app, true)
+  })
+  .await??;
+  let _ = app.emit("telegram://paired", serde_json::json!({ "chatId": chat_id }));
+  Ok(())
 }
 
-#[derive(Debug, Deserialize)]
-struct TgResponse<T> {
-  ok: bool,
-  result: Option<T>,
-  description: Option<String>,
+async fn with_access_inbox<R>(
+  runtime: &TelegramRuntime,
+  app: &AppHandle,
+  f: impl FnOnce(&mut AccessInbox) -> R,
+) -> Result<R, String> {
+  let mut guard = runtime.inner.access_inbox.lock().await;
+  let inbox = match guard.take() {
+    Some(inbox) => inbox,
+    None => AccessInbox::load(app)?,
+  };
+  Ok(f(guard.insert(inbox)))
 }
 
-#[derive(Debug, Deserialize)]
-struct TgUser {
-  username: Option<String>,
+// Adds a message from a chat without access to the access-request inbox, under its sender; `note`
+// comes from /request_access (which also re-opens a denied request).
+async fn record_access_request(runtime: &TelegramRuntime, m: &TgMessage, note: Option<String>) -> Result<(), String> {
+  let app = runtime.inner.app.read().await.clone().ok_or_else(|| "app handle missing".to_string())?;
+  let now = time::now_unix_ms();
+  let user_id = m.from.as_ref().map(|u| u.id);
+  let (req, changed) = with_access_inbox(runtime, &app, |inbox| {
+    let existing = inbox.requests.iter().position(|r| r.chat_id == m.chat.id && r.user_id == user_id);
+    let is_new = existing.is_none();
+    let idx = match existing {
+      Some(i) => i,
+      None => {
+        if inbox.requests.len() >= ACCESS_REQUESTS_MAX {
+          let oldest = inbox.requests.iter().enumerate().min_by_key(|(_, r)| r.last_seen_unix_ms).map(|(i, _)| i);
+          if let Some(oldest) = oldest {
+            inbox.requests.remove(oldest);
+          }
+        }
+        inbox.requests.push(TelegramAccessRequest {
+          chat_id: m.chat.id,
+          chat_title: None,
+          user_id,
+          name: String::new(),
+          username: None,
+          first_seen_unix_ms: now,
+          last_seen_unix_ms: now,
+          message_count: 0,
+          note: None,
+          status: AccessRequestStatus::Pending,
+        });
+        inbox.requests.len() - 1
+      }
+    };
+    let req = &mut inbox.requests[idx];
+    req.chat_title = m.chat.title.clone();
+    if let Some(u) = m.from.as_ref() {
+      req.name = user_display_name(u);
+      req.username = u.username.clone();
+    }
+    req.last_seen_unix_ms = now;
+    req.message_count += 1;
+    let changed = is_new || note.is_some();
+    if note.is_some() {
+      req.note = note;
+      req.status = AccessRequestStatus::Pending;
+    }
+    let req = req.clone();
+    inbox.save(&app, changed).map(|_| (req, changed))
+  })
+  .await??;
+  if changed {
+    let _ = app.emit("telegram://access_request", &req);
+  }
+  Ok(())
+}
+
+// The single access check for incoming messages: commands need `access::command_role`, anything
+// that reaches Codex (plain text, /codex, voice, attachments) the role the chat's sandbox and
+// approval policy require. Strangers' plain messages are ignored silently; commands get an answer.
+async fn authorize_message(
+  runtime: &TelegramRuntime,
+  client: &Client,
+  bot: &TgBot,
+  cfg: &AppConfig,
+  m: &TgMessage,
+) -> bool {
+  let chat = m.chat_key();
+  let user_id = m.from.as_ref().map(|u| u.id);
+  let text = m.text.as_deref().or(m.caption.as_deref()).unwrap_or("").trim();
+  let (cmd, _) = parse_command(text);
+  let role = access::role_for(&cfg.telegram, user_id, chat.chat_id);
+  // /request_access records its own entry; a successful /pair would leave a stale one.
+  if role.is_none() && !matches!(cmd.as_deref(), Some("/request_access" | "/pair")) {
+    if let Err(e) = record_access_request(runtime, m, None).await {
+      log::info!("telegram: record access request failed: {e}");
+    }
+  }
+  let required = match cmd.as_deref() {
+    None | Some("/codex") => Some(prompt_role(runtime, chat).await),
+    Some(c) => access::command_role(c),
+  };
+  let Some(required) = required else {
+    return true;
+  };
+  if role.is_some_and(|r| r >= required) {
+    return true;
+  }
+
+  runtime.inner.logs.push(
+    logbus::LogLevel::Warn,
+    "telegram",
+    format!(
+      "access denied chat_id={chat} user_id={} cmd={} need={}",
+      user_id.unwrap_or(0),
+      cmd.as_deref().unwrap_or("(text)"),
+      access::role_label(required)
+    ),
+  );
+  if cmd.is_some() || role.is_some() {
+    let body = denied_text(role, required);
+    if let Err(e) = tg_send_message(client, bot, chat, &body, Some(m.message_id)).await {
+      log::info!("telegram: send deny failed: {e}");
+    }
+  }
+  false
+}
+
+async fn prompt_role(runtime: &TelegramRuntime, chat: ChatKey) -> Role {
+  let settings = runtime.inner.codex.effective_chat_settings(Some(chat)).await;
+  access::sandbox_role(settings.sandbox.unwrap_or_default())
+    .max(access::approval_role(settings.approval_policy.unwrap_or_default()))
+}
+
+fn denied_text(role: Option<Role>, required: Role) -> String {
+  match role {
+    None => NO_ACCESS_MSG.to_string(),
+    Some(_) => format!("Недостатньо прав: потрібна роль {}.", access::role_label(required)),
+  }
+}
+
+// Codex input for a message: the replied-to text as quoted context, then the prompt (prefixed with
+// the author in groups).
+async fn compose_prompt(runtime: &TelegramRuntime, m: &TgMessage, text: &str) -> String {
+  let prompt = with_author(group_author(m).as_deref(), text);
+  let Some(r) = m.reply_to_message.as_deref() else {
+    return prompt;
+  };
+  let full = m
+    .quote
+    .as_ref()
+    .map(|q| q.text.as_str())
+    .or(r.text.as_deref())
+    .or(r.caption.as_deref())
+    .map(str::trim)
+    .unwrap_or("");
+  if full.is_empty() {
+    return prompt;
+  }
+  let who = if answer_link(runtime, m.chat_key(), r.message_id).await.is_some() {
+    "your earlier answer".to_string()
+  } else {
+    sender_label(r).unwrap_or_else(|| "a message".to_string())
+  };
+  let mut quoted: String = full.chars().take(REPLY_CONTEXT_MAX_CHARS).collect();
+  if quoted.len() < full.len() {
+    quoted.push('…');
+  }
+  let quoted = quoted.lines().map(|l| format!("> {l}")).collect::<Vec<_>>().join("\n");
+  format!("Replying to {who}:\n{quoted}\n\n{prompt}")
+}
+
+// With `reply_follows_thread`, a prompt sent as a reply to an answer from another thread moves the
+// chat to that thread first. Skipped while a reply is running: queued prompts stay where they are.
+async fn follow_reply_thread(runtime: &TelegramRuntime, client: &Client, bot: &TgBot, cfg: &AppConfig, m: &TgMessage) {
+  if !cfg.telegram.reply_follows_thread {
+    return;
+  }
+  let (cmd, _) = parse_command(m.text.as_deref().or(m.caption.as_deref()).unwrap_or("").trim());
+  if !matches!(cmd.as_deref(), None | Some("/codex")) {
+    return;
+  }
+  let chat = m.chat_key();
+  let Some(link) = m.reply_to_message.as_deref() else { return };
+  let Some(link) = answer_link(runtime, chat, link.message_id).await else { return };
+  let codex = &runtime.inner.codex;
+  if codex.get_chat_thread(chat).await.as_deref() == Some(link.thread_id.as_str()) {
+    return;
+  }
+  if runtime.inner.queues.lock().await.get(&chat).is_some_and(|q| q.running) {
+    return;
+  }
+  let body = match codex.attach_chat_to_thread(chat, link.thread_id.clone()).await {
+    Ok(()) => {
+      runtime.inner.logs.push(
+        logbus::LogLevel::Info,
+        "telegram",
+        format!("reply follows thread chat_id={chat} thread_id={}", link.thread_id),
+      );
+      "Продовжую в діалозі, з якого ця відповідь.".to_string()
+    }
+    Err(e) => format!("Не вдалося перейти в діалог цієї відповіді, продовжую в поточному: {e}"),
+  };
+  if let Err(e) = tg_send_message(client, bot, chat, &body, Some(m.message_id)).await {
+    log::info!("telegram: send thread switch note failed: {e}");
+  }
+}
+
+// An edited prompt: still queued → updated in place; being answered → the turn is stopped and
+// re-run with the new text; already answered → offers a re-run that replaces the thread's last turn.
+// Only text prompts are handled; edited captions and commands are ignored.
+async fn handle_edited_message(runtime: &TelegramRuntime, client: &Client, bot: &TgBot, m: &TgMessage) {
+  let chat = m.chat_key();
+  let message_id = m.message_id;
+  let text = m.text.as_deref().map(str::trim).unwrap_or("");
+  let text = match parse_command(text) {
+    (None, _) if !text.is_empty() => text.to_string(),
+    (Some(cmd), Some(rest)) if cmd == "/codex" => rest,
+    _ => return,
+  };
+  let prompt = compose_prompt(runtime, m, &text).await;
+  let link = prompt_link(runtime, chat, message_id).await;
+  runtime
+    .inner
+    .logs
+    .push(logbus::LogLevel::Info, "telegram", format!("edited prompt chat_id={chat} message_id={message_id}"));
+
+  enum Found {
+    Queued,
+    Running,
+    Answered,
+  }
+  let found = {
+    let mut queues = runtime.inner.queues.lock().await;
+    match queues.get_mut(&chat) {
+      Some(q) if q.items.iter().any(|i| i.message_id == message_id) => {
+        for item in q.items.iter_mut().filter(|i| i.message_id == message_id) {
+          item.prompt = prompt.clone();
+        }
+        Found::Queued
+      }
+      Some(q) if q.running && q.current == Some(message_id) => {
+        q.items.push_front(QueuedPrompt {
+          message_id,
+          prompt: prompt.clone(),
+          images: vec![],
+          rollback_turn: link.as_ref().map(|l| l.turn_id.clone()),
+        });
+        Found::Running
+      }
+      _ => Found::Answered,
+    }
+  };
+
+  let (body, markup) = match found {
+    Found::Queued => ("Оновив повідомлення в черзі.".to_string(), None),
+    Found::Running => {
+      if let Err(e) = runtime.inner.codex.interrupt_turn(chat).await {
+        runtime
+          .inner
+          .logs
+          .push(logbus::LogLevel::Warn, "telegram", format!("interrupt failed chat_id={chat}: {e}"));
+      }
+      ("Повідомлення змінено — зупиняю відповідь і перезапускаю з новим текстом.".to_string(), None)
+    }
+    Found::Answered => {
+      let current = runtime.inner.codex.get_chat_thread(chat).await;
+      match link {
+        Some(link) if current.as_deref() == Some(link.thread_id.as_str()) => {
+          {
+            let mut reruns = runtime.inner.pending_reruns.lock().await;
+            reruns.retain(|(c, _), _| *c != chat);
+            reruns.insert((chat, message_id), prompt);
+          }
+          let markup = serde_json::json!({
+            "inline_keyboard": [[
+              { "text": "Перезапустити", "callback_data": format!("re:y:{message_id}") },
+              { "text": "Не треба", "callback_data": format!("re:n:{message_id}") }
+            ]]
+          });
+          (
+            "Повідомлення змінено. Перезапустити з новим текстом? Попередня відповідь зникне з історії діалогу.".to_string(),
+            Some(markup),
+          )
+        }
+        _ => ("Це повідомлення вже не перезапустити — надішли виправлений текст окремим повідомленням.".to_string(), None),
+      }
+    }
+  };
+  let res = match markup {
+    Some(markup) => tg_send_message_markup(client, bot, chat, &body, Some(message_id), markup).await.map(|_| ()),
+    None => tg_send_message(client, bot, chat, &body, Some(message_id)).await,
+  };
+  if let Err(e) = res {
+    log::info!("telegram: send edit reply failed: {e}");
+  }
+}
+
+async fn answer_link(runtime: &TelegramRuntime, chat: ChatKey, message_id: i64) -> Option<TurnLink> {
+  let links = runtime.inner.answer_links.lock().await;
+  links.get(&chat)?.iter().find(|l| l.message_id == message_id).cloned()
+}
+
+async fn prompt_link(runtime: &TelegramRuntime, chat: ChatKey, message_id: i64) -> Option<TurnLink> {
+  let links = runtime.inner.prompt_links.lock().await;
+  links.get(&chat)?.iter().rev().find(|l| l.message_id == message_id).cloned()
+}
+
+async fn remember_prompt(runtime: &TelegramRuntime, chat: ChatKey, message_id: i64, thread_id: &str, turn_id: &str) {
+  // A new turn: an older re-run offer could no longer roll back its answer.
+  runtime.inner.pending_reruns.lock().await.retain(|(c, _), _| *c != chat);
+  let mut links = runtime.inner.prompt_links.lock().await;
+  let list = links.entry(chat).or_default();
+  list.push(TurnLink {
+    message_id,
+    thread_id: thread_id.to_string(),
+    turn_id: turn_id.to_string(),
+  });
+  if list.len() > TURN_LINKS_PER_CHAT {
+    let extra = list.len() - TURN_LINKS_PER_CHAT;
+    list.drain(..extra);
+  }
+}
+
+async fn remember_answer(runtime: &TelegramRuntime, chat: ChatKey, message_ids: &[i64], thread_id: &str, turn_id: &str) {
+  if message_ids.is_empty() {
+    return;
+  }
+  let Some(app) = runtime.inner.app.read().await.clone() else { return };
+  let mut links = runtime.inner.answer_links.lock().await;
+  let list = links.entry(chat).or_default();
+  list.extend(message_ids.iter().map(|&message_id| TurnLink {
+    message_id,
+    thread_id: thread_id.to_string(),
+    turn_id: turn_id.to_string(),
+  }));
+  if list.len() > TURN_LINKS_PER_CHAT {
+    let extra = list.len() - TURN_LINKS_PER_CHAT;
+    list.drain(..extra);
+  }
+  if let Err(e) = save_answer_links(&app, &links) {
+    runtime.inner.logs.push(logbus::LogLevel::Warn, "telegram", format!("answer links: {e}"));
+  }
+}
+
+// Downloads a photo or document into the chat inbox and sends it to Codex with the caption as the
+// prompt: photos (and image documents) as `localImage` inputs, other files as a path reference.
+async fn handle_attachment_message(
+  runtime: &TelegramRuntime,
+  client: &Client,
+  bot: &TgBot,
+  cfg: &AppConfig,
+  msg: &TgMessage,
+) {
+  let chat = msg.chat_key();
+  let message_id = msg.message_id;
+
+  let (file_id, file_name, mime, size) = if let Some(doc) = msg.document.as_ref() {
+    let mime = doc.mime_type.clone().unwrap_or_else(|| "application/octet-stream".to_string());
+    let name = doc.file_name.clone().unwrap_or_else(|| "file".to_string());
+    (doc.file_id.clone(), name, mime, doc.file_size)
+  } else {
+    // Telegram sends several sizes of the same photo; take the largest one that fits.
+    let sizes = msg.photo.as_deref().unwrap_or(&[]);
+    let Some(best) = sizes
+      .iter()
+      .filter(|p| p.file_size.unwrap_or(0) <= cfg.telegram.attachment_max_bytes)
+      .max_by_key(|p| p.file_size.unwrap_or(0))
+      .or_else(|| sizes.last())
+    else {
+      return;
+    };
+    (best.file_id.clone(), "photo.jpg".to_string(), "image/jpeg".to_string(), best.file_size)
+  };
+
+  runtime.inner.logs.push(
+    logbus::LogLevel::Info,
+    "telegram",
+    format!("attachment chat_id={chat} mime={mime} size={}", size.unwrap_or(0)),
+  );
+
+  let refuse = if !mime_allowed(&cfg.telegram.attachment_mime_allowlist, &mime) {
+    Some(format!("Такий тип файлу не приймаю ({mime})."))
+  } else if size.unwrap_or(0) > cfg.telegram.attachment_max_bytes {
+    Some(format!(
+      "Файл завеликий: {} МБ (ліміт {} МБ).",
+      size.unwrap_or(0) / (1024 * 1024),
+      cfg.telegram.attachment_max_bytes / (1024 * 1024)
+    ))
+  } else {
+    None
+  };
+  if let Some(body) = refuse {
+    if let Err(e) = tg_send_message(client, bot, chat, &body, Some(message_id)).await {
+      log::info!("telegram: send attachment refusal failed: {e}");
+    }
+    return;
+  }
+
+  // The download can take a while; keep it off the update loop so other chats aren't held up.
+  let (runtime, client, bot, cfg, msg) = (runtime.clone(), client.clone(), bot.clone(), cfg.clone(), msg.clone());
+  tauri::async_runtime::spawn(async move {
+    let saved = match save_attachment(&runtime, &client, &bot, &cfg, chat, message_id, &file_id, &file_name).await {
+      Ok(p) => p,
+      Err(e) => {
+        runtime
+          .inner
+          .logs
+          .push(logbus::LogLevel::Warn, "telegram", format!("attachment download failed: {e}"));
+        let body = format!("Не вдалося завантажити файл: {e}");
+        if let Err(e) = tg_send_message(&client, &bot, chat, &body, Some(message_id)).await {
+          log::info!("telegram: send attachment error failed: {e}");
+        }
+        return;
+      }
+    };
+    let saved = saved.to_string_lossy().to_string();
+
+    let caption = msg.caption.as_deref().map(str::trim).unwrap_or("");
+    let (prompt, images) = if mime.starts_with("image/") {
+      (caption.to_string(), vec![saved])
+    } else {
+      let text = if caption.is_empty() { "Подивись на вкладений файл." } else { caption };
+      (format!("{text}\n\nAttached file: {saved}"), vec![])
+    };
+    let prompt = compose_prompt(&runtime, &msg, &prompt).await;
+
+    runtime.inner.logs.push(logbus::LogLevel::Info, "telegram", format!("codex request chat_id={chat}"));
+    submit_codex_prompt(&runtime, &client, &bot, &cfg, chat, QueuedPrompt::new(message_id, prompt, images)).await;
+  });
+}
+
+// Transcribes a voice note (or audio file) with the local STT command, echoes the transcript and
+// then handles it like a typed message.
+async fn handle_voice_message(
+  runtime: &TelegramRuntime,
+  client: &Client,
+  bot: &TgBot,
+  cfg: &AppConfig,
+  msg: &TgMessage,
+) {
+  let chat = msg.chat_key();
+  let message_id = msg.message_id;
+  let Some(audio) = msg.voice.as_ref().or(msg.audio.as_ref()) else { return; };
+
+  let refuse = if !stt::is_configured(&cfg.stt) {
+    Some("Розпізнавання голосу не налаштоване.".to_string())
+  } else if audio.duration.unwrap_or(0) > cfg.stt.max_duration_sec {
+    Some(format!("Голосове задовге (ліміт {} с).", cfg.stt.max_duration_sec))
+  } else if audio.file_size.unwrap_or(0) > cfg.telegram.attachment_max_bytes {
+    Some("Аудіофайл завеликий.".to_string())
+  } else {
+    None
+  };
+  if let Some(body) = refuse {
+    if let Err(e) = tg_send_message(client, bot, chat, &body, Some(message_id)).await {
+      log::info!("telegram: send voice refusal failed: {e}");
+    }
+    return;
+  }
+
+  // Download and transcription can take a while; keep them off the update loop.
+  let audio = audio.clone();
+  let (runtime, client, bot, cfg, msg) = (runtime.clone(), client.clone(), bot.clone(), cfg.clone(), msg.clone());
+  tauri::async_runtime::spawn(async move {
+    let _ = tg_send_chat_action(&client, &bot, chat, "typing").await;
+    let file_name = audio.file_name.unwrap_or_else(|| "voice.ogg".to_string());
+    let transcript = match save_attachment(&runtime, &client, &bot, &cfg, chat, message_id, &audio.file_id, &file_name).await {
+      Ok(path) => {
+        let res = stt::transcribe(&cfg.stt, &path).await;
+        let _ = tokio::fs::remove_file(&path).await;
+        res
+      }
+      Err(e) => Err(e),
+    };
+    let transcript = match transcript {
+      Ok(t) => t,
+      Err(e) => {
+        runtime
+          .inner
+          .logs
+          .push(logbus::LogLevel::Warn, "telegram", format!("voice transcription failed: {e}"));
+        let body = format!("Не вдалося розпізнати голосове: {e}");
+        if let Err(e) = tg_send_message(&client, &bot, chat, &body, Some(message_id)).await {
+          log::info!("telegram: send voice error failed: {e}");
+        }
+        return;
+      }
+    };
+    runtime
+      .inner
+      .logs
+      .push(logbus::LogLevel::Info, "telegram", format!("voice transcribed chat_id={chat} chars={}", transcript.chars().count()));
+
+    let echo = format!("Розпізнано: {transcript}");
+    if let Err(e) = tg_send_message(&client, &bot, chat, &echo, Some(message_id)).await {
+      log::info!("telegram: send transcript failed: {e}");
+    }
+
+    if answer_pending_question(&runtime, &client, &bot, chat, &transcript).await {
+      return;
+    }
+    runtime.inner.logs.push(logbus::LogLevel::Info, "telegram", format!("codex request chat_id={chat}"));
+    let prompt = compose_prompt(&runtime, &msg, &transcript).await;
+    submit_codex_prompt(&runtime, &client, &bot, &cfg, chat, QueuedPrompt::new(message_id, prompt, vec![])).await;
+  });
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn save_attachment(
+  runtime: &TelegramRuntime,
+  client: &Client,
+  bot: &TgBot,
+  cfg: &AppConfig,
+  chat: ChatKey,
+  message_id: i64,
+  file_id: &str,
+  file_name: &str,
+) -> Result<PathBuf, String> {
+  let app = runtime.inner.app.read().await.clone().ok_or_else(|| "app handle missing".to_string())?;
+  let bound = runtime.inner.codex.chat_workspace(chat).await.map(|w| w.path);
+  let dir = inbox_dir(&app, cfg, bound.as_deref(), chat.chat_id)?;
+  tokio::fs::create_dir_all(&dir)
+    .await
+    .map_err(|e| format!("create inbox dir failed: {e}"))?;
+  let path = dir.join(format!("{message_id}-{}", sanitize_file_name(file_name)));
+  tg_download_file(client, bot, file_id, cfg.telegram.attachment_max_bytes, &path).await?;
+  Ok(path)
+}
+
+// `<workspace>/.telegram-inbox/<chat_id>` so Codex can reach the files from its sandbox (the
+// chat's bound workspace first); the app data dir when no workspace is configured.
+fn inbox_dir(app: &AppHandle, cfg: &AppConfig, bound: Option<&str>, chat_id: i64) -> Result<PathBuf, String> {
+  let root = match bound.or(cfg.codex.workspace_dir.as_deref()).map(str::trim).filter(|s| !s.is_empty()) {
+    Some(ws) => Path::new(ws).join(".telegram-inbox"),
+    None => paths::telegram_inbox_dir(app)?,
+  };
+  Ok(root.join(chat_id.to_string()))
+}
+
+fn sanitize_file_name(name: &str) -> String {
+  let base = Path::new(name)
+    .file_name()
+    .map(|n| n.to_string_lossy().to_string())
+    .unwrap_or_default();
+  let cleaned: String = base
+    .chars()
+    .map(|c| if c.is_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+    .collect();
+  let cleaned = cleaned.trim_start_matches('.').to_string();
+  if cleaned.is_empty() {
+    "file".to_string()
+  } else {
+    cleaned
+  }
+}
+
+fn mime_allowed(allowlist: &[String], mime: &str) -> bool {
+  let mime = mime.trim().to_ascii_lowercase();
+  allowlist.iter().any(|pat| {
+    let pat = pat.trim().to_ascii_lowercase();
+    match pat.strip_suffix("/*") {
+      Some(family) => mime.split('/').next() == Some(family),
+      None => pat == "*" || pat == mime,
+    }
+  })
+}
+
+// Starts a Codex reply right away, or queues/rejects the prompt per `busy_policy` when the chat
+// already has one running.
+async fn submit_codex_prompt(
+  runtime: &TelegramRuntime,
+  client: &Client,
+  bot: &TgBot,
+  cfg: &AppConfig,
+  chat: ChatKey,
+  next: QueuedPrompt,
+) {
+  let policy = cfg.telegram.busy_policy;
+  let max_depth = cfg.telegram.queue_max_depth.clamp(1, 50);
+  let message_id = next.message_id;
+  let mut next = Some(next);
+  let reply = {
+    let mut queues = runtime.inner.queues.lock().await;
+    let q = queues.entry(chat).or_default();
+    if !q.running {
+      q.running = true;
+      q.current = Some(message_id);
+      None
+    } else if policy == BusyPolicy::Reject {
+      Some("Зачекай: обробляю попереднє повідомлення.".to_string())
+    } else if q.items.len() >= max_depth {
+      Some(format!("Черга заповнена ({max_depth}). Зачекай або очисти її: /queue clear"))
+    } else {
+      q.items.extend(next.take());
+      let pos = q.items.len();
+      Some(if policy == BusyPolicy::Merge {
+        format!("Додав у чергу ({pos}). Після поточної відповіді надішлю всі повідомлення з черги разом.")
+      } else {
+        format!("Додав у чергу, позиція {pos}. Переглянути: /queue")
+      })
+    }
+  };
+
+  match (reply, next) {
+    (None, Some(next)) => {
+      spawn_codex_reply(runtime.clone(), client.clone(), bot.clone(), chat, next);
+    }
+    (None, None) => {}
+    (Some(body), _) => {
+      runtime
+        .inner
+        .logs
+        .push(logbus::LogLevel::Info, "telegram", format!("codex busy chat_id={chat} policy={policy:?}"));
+      if let Err(e) = tg_send_message(client, bot, chat, &body, Some(message_id)).await {
+        log::info!("telegram: send queue reply failed: {e}");
+      }
+    }
+  }
+}
+
+// Called when a reply finishes: starts the next queued prompt (or the merged queue) for the chat.
+async fn start_next_queued(runtime: TelegramRuntime, client: Client, bot: TgBot, chat: ChatKey) {
+  let policy = runtime.inner.config.read().await.telegram.busy_policy;
+  let next = {
+    let mut queues = runtime.inner.queues.lock().await;
+    let Some(q) = queues.get_mut(&chat) else { return; };
+    let next = if policy == BusyPolicy::Merge && q.items.len() > 1 {
+      let items: Vec<QueuedPrompt> = q.items.drain(..).collect();
+      let message_id = items.last().map(|i| i.message_id).unwrap_or(0);
+      let images = items.iter().flat_map(|i| i.images.clone()).collect();
+      let rollback_turn = items.first().and_then(|i| i.rollback_turn.clone());
+      let prompt = items
+        .into_iter()
+        .map(|i| i.prompt)
+        .filter(|p| !p.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+      Some(QueuedPrompt {
+        message_id,
+        prompt,
+        images,
+        rollback_turn,
+      })
+    } else {
+      q.items.pop_front()
+    };
+    match next.as_ref() {
+      Some(n) => q.current = Some(n.message_id),
+      None => {
+        queues.remove(&chat);
+      }
+    }
+    next
+  };
+  let Some(next) = next else { return; };
+  runtime
+    .inner
+    .logs
+    .push(logbus::LogLevel::Info, "telegram", format!("codex request (queued) chat_id={chat}"));
+  spawn_codex_reply(runtime, client, bot, chat, next);
+}
+
+// /login: starts a ChatGPT sign-in, sends its URL as a button and reports the outcome to this chat
+// in the background; on success every chat with prompts that failed for lack of sign-in is
+// offered a retry.
+async fn start_remote_login(runtime: &TelegramRuntime, client: &Client, bot: &TgBot, chat: ChatKey, message_id: i64) {
+  let codex = runtime.inner.codex.clone();
+  let (auth_url, login_id) = match codex.login_chatgpt().await {
+    Ok(v) => v,
+    Err(e) => {
+      let body = format!("Не вдалося почати вхід: {e}");
+      if let Err(e) = tg_send_message(client, bot, chat, &body, Some(message_id)).await {
+        log::info!("telegram: send /login error failed: {e}");
+      }
+      return;
+    }
+  };
+  runtime.inner.logs.push(logbus::LogLevel::Info, "telegram", format!("remote login started chat_id={chat}"));
+  let completed = codex.watch_login(&login_id).await;
+  let markup = serde_json::json!({ "inline_keyboard": [[{ "text": "Увійти в ChatGPT", "url": auth_url }]] });
+  let body = "Відкрий посилання й увійди. Я напишу, коли вхід завершиться.";
+  if let Err(e) = tg_send_message_markup(client, bot, chat, body, Some(message_id), markup).await {
+    log::info!("telegram: send /login link failed: {e}");
+  }
+
+  let (runtime, client, bot) = (runtime.clone(), client.clone(), bot.clone());
+  tauri::async_runtime::spawn(async move {
+    let res = codex.wait_for_login(&login_id, completed, LOGIN_WAIT).await;
+    let body = match &res {
+      Ok(()) => "Вхід у Codex виконано.".to_string(),
+      Err(e) => format!("Вхід не вдався: {e}"),
+    };
+    let level = if res.is_ok() { logbus::LogLevel::Info } else { logbus::LogLevel::Warn };
+    runtime.inner.logs.push(level, "telegram", format!("remote login finished chat_id={chat}: {body}"));
+    if let Err(e) = tg_send_message(&client, &bot, chat, &body, Some(message_id)).await {
+      log::info!("telegram: send /login result failed: {e}");
+    }
+    if res.is_ok() {
+      offer_sign_in_retries(&runtime, &client, &bot).await;
+    }
+  });
+}
+
+async fn offer_sign_in_retries(runtime: &TelegramRuntime, client: &Client, bot: &TgBot) {
+  let chats: Vec<(ChatKey, usize)> = {
+    let waiting = runtime.inner.awaiting_sign_in.lock().await;
+    waiting.iter().filter(|(_, l)| !l.is_empty()).map(|(c, l)| (*c, l.len())).collect()
+  };
+  for (chat, n) in chats {
+    let body = format!("Codex знову доступний. Повторити повідомлення, що не пройшли через вхід ({n})?");
+    let markup = serde_json::json!({
+      "inline_keyboard": [[
+        { "text": "Повторити", "callback_data": "rt:y" },
+        { "text": "Не треба", "callback_data": "rt:n" }
+      ]]
+    });
+    if let Err(e) = tg_send_message_markup(client, bot, chat, &body, None, markup).await {
+      log::info!("telegram: send retry offer failed: {e}");
+    }
+  }
+}
+
+// Owner commands for checking on the app remotely.
+async fn admin_command(
+  runtime: &TelegramRuntime,
+  client: &Client,
+  bot: &TgBot,
+  chat: ChatKey,
+  message_id: i64,
+  cmd: &str,
+  rest: Option<&str>,
+) {
+  let body = match cmd {
+    "/status" => status_text(runtime).await,
+    "/doctor" | "/restart" => {
+      // Both wait on Codex (a shell-out, an app-server restart); reply from a task so the update
+      // loop keeps going.
+      let (runtime, client, bot, cmd) = (runtime.clone(), client.clone(), bot.clone(), cmd.to_string());
+      tauri::async_runtime::spawn(async move {
+        let body = codex_admin_reply(&runtime, &client, &bot, chat, message_id, &cmd).await;
+        if let Err(e) = tg_send_message_series(&client, &bot, chat, &body, Some(message_id)).await {
+          log::info!("telegram: send {cmd} reply failed: {e}");
+        }
+      });
+      return;
+    }
+    _ => {
+      let (limit, source) = parse_logs_args(rest.unwrap_or(""));
+      let text = logs_text(&runtime.inner.logs, limit, source.as_deref(), &bot.token);
+      if text.chars().count() <= LOGS_INLINE_MAX_CHARS {
+        text
+      } else {
+        let caption = format!("Останні записи журналу ({limit})");
+        match tg_send_document(client, bot, chat, "logs.txt", text.into_bytes(), Some(&caption), Some(message_id)).await {
+          Ok(_) => return,
+          Err(e) => format!("Не вдалося надіслати файл: {e}"),
+        }
+      }
+    }
+  };
+  if let Err(e) = tg_send_message_series(client, bot, chat, &body, Some(message_id)).await {
+    log::info!("telegram: send {cmd} reply failed: {e}");
+  }
+}
+
+async fn codex_admin_reply(
+  runtime: &TelegramRuntime,
+  client: &Client,
+  bot: &TgBot,
+  chat: ChatKey,
+  message_id: i64,
+  cmd: &str,
+) -> String {
+  let codex = &runtime.inner.codex;
+  if cmd == "/doctor" {
+    return doctor_text(&codex.doctor().await);
+  }
+  if let Err(e) = tg_send_message(client, bot, chat, "Перезапускаю Codex…", Some(message_id)).await {
+    log::info!("telegram: send /restart ack failed: {e}");
+  }
+  runtime.inner.logs.push(logbus::LogLevel::Warn, "telegram", format!("codex restart requested chat_id={chat}"));
+  match codex.restart().await {
+    Ok(()) => "Codex перезапущено.".to_string(),
+    Err(e) => format!("Не вдалося перезапустити Codex: {}", redact_secrets(&e, &bot.token)),
+  }
+}
+
+async fn status_text(runtime: &TelegramRuntime) -> String {
+  let tg = runtime.status().await;
+  let cx = runtime.inner.codex.status().await;
+  let (running, queued) = {
+    let queues = runtime.inner.queues.lock().await;
+    (queues.values().filter(|q| q.running).count(), queues.values().map(|q| q.items.len()).sum::<usize>())
+  };
+  let yes_no = |b: bool| if b { "так" } else { "ні" };
+
+  let mut out = format!(
+    "Telegram\nпрацює: {}\nбот: @{}",
+    yes_no(tg.running),
+    tg.bot_username.as_deref().unwrap_or("?")
+  );
+  if let Some(ts) = tg.last_poll_unix_ms {
+    out.push_str(&format!("\nостанній зв'язок: {} с тому", time::now_unix_ms().saturating_sub(ts) / 1000));
+  }
+  if let Some(e) = tg.last_error.as_deref() {
+    out.push_str(&format!("\nпомилка: {e}"));
+  }
+  out.push_str(&format!(
+    "\n\nCodex\nзапущено: {}\nініціалізовано: {}\nвхід: {}",
+    yes_no(cx.running),
+    yes_no(cx.initialized),
+    cx.auth_mode.as_deref().unwrap_or("немає")
+  ));
+  if let Some(e) = cx.last_error.as_deref() {
+    out.push_str(&format!("\nпомилка: {e}"));
+  }
+  out.push_str(&format!("\n\nВідповідей зараз: {running}\nУ черзі: {queued}"));
+  out
+}
+
+fn doctor_text(d: &CodexDoctor) -> String {
+  let line = |name: &str, ok: bool, detail: Option<&str>| {
+    let mark = if ok { "✓" } else { "✗" };
+    match detail.filter(|s| !s.is_empty()) {
+      Some(detail) => format!("{mark} {name}: {detail}"),
+      None => format!("{mark} {name}"),
+    }
+  };
+  [
+    line("node", d.node_ok, d.node_path.as_deref()),
+    line("npm", d.npm_ok, d.npm_path.as_deref()),
+    line("codex", d.codex_ok, d.codex_version.as_deref()),
+    line("вбудований codex", d.local_codex_ok, d.local_codex_version.as_deref()),
+  ]
+  .join("\n")
+}
+
+// `/logs [n] [source]` in any order: a number is the count, anything else filters by source.
+fn parse_logs_args(rest: &str) -> (usize, Option<String>) {
+  let mut limit = LOGS_DEFAULT_COUNT;
+  let mut source = None;
+  for arg in rest.split_whitespace() {
+    match arg.parse::<usize>() {
+      Ok(n) => limit = n.clamp(1, LOGS_MAX_COUNT),
+      Err(_) => source = Some(arg.to_ascii_lowercase()),
+    }
+  }
+  (limit, source)
+}
+
+// Oldest first; `source` matches by prefix so "codex" includes "codex(app-server)".
+fn logs_text(logs: &logbus::LogBus, limit: usize, source: Option<&str>, token: &str) -> String {
+  let mut entries: Vec<logbus::LogEntry> = logs
+    .list(usize::MAX)
+    .into_iter()
+    .filter(|e| source.is_none_or(|s| e.source.to_ascii_lowercase().starts_with(s)))
+    .take(limit)
+    .collect();
+  if entries.is_empty() {
+    return "Журнал порожній.".to_string();
+  }
+  entries.reverse();
+  entries
+    .iter()
+    .map(|e| {
+      let secs = (e.ts_unix_ms / 1000) % 86_400;
+      let level = match e.level {
+        logbus::LogLevel::Info => "INFO",
+        logbus::LogLevel::Warn => "WARN",
+        logbus::LogLevel::Error => "ERROR",
+      };
+      format!(
+        "{:02}:{:02}:{:02} {level} {}: {}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        e.source,
+        redact_secrets(&e.msg, token)
+      )
+    })
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+fn queue_summary(items: &VecDeque<QueuedPrompt>) -> String {
+  if items.is_empty() {
+    return "Черга порожня.".to_string();
+  }
+  let mut out = format!("У черзі: {}\n", items.len());
+  for (i, it) in items.iter().enumerate() {
+    let line = it.prompt.lines().next().unwrap_or("").trim();
+    let line = if line.is_empty() && !it.images.is_empty() { "[фото]" } else { line };
+    let preview: String = line.chars().take(60).collect();
+    let ellipsis = if line.chars().count() > 60 || it.prompt.lines().count() > 1 { "…" } else { "" };
+    out.push_str(&format!("\n{}. {preview}{ellipsis}", i + 1));
+  }
+  out.push_str("\n\nОчистити: /queue clear");
+  out
+}
+
+fn spawn_codex_reply(runtime: TelegramRuntime, client: Client, bot: TgBot, chat: ChatKey, next: QueuedPrompt) {
+  tauri::async_runtime::spawn(async move {
+    run_codex_reply(runtime.clone(), client.clone(), bot.clone(), chat, next).await;
+    start_next_queued(runtime, client, bot, chat).await;
+  });
+}
+
+async fn run_codex_reply(runtime: TelegramRuntime, client: Client, bot: TgBot, chat: ChatKey, next: QueuedPrompt) {
+  let QueuedPrompt {
+    message_id,
+    prompt,
+    images,
+    rollback_turn,
+  } = next;
+  let codex = runtime.inner.codex.clone();
+  let logs = runtime.inner.logs.clone();
+  let (typing_tx, mut typing_rx) = watch::channel(false);
+
+  // Standard Telegram loader while Codex works.
+  let client3 = client.clone();
+  let bot3 = bot.clone();
+  let logs3 = logs.clone();
+  tauri::async_runtime::spawn(async move {
+    loop {
+      if *typing_rx.borrow() {
+        break;
+      }
+      if let Err(e) = tg_send_chat_action(&client3, &bot3, chat, "typing").await {
+        logs3.push(logbus::LogLevel::Warn, "telegram", format!("sendChatAction failed: {e}"));
+        break;
+      }
+      tokio::select! {
+        _ = typing_rx.changed() => break,
+        _ = tokio::time::sleep(Duration::from_secs(4)) => {}
+      }
+    }
+  });
+
+  if let Some(turn_id) = rollback_turn {
+    if let Err(e) = codex.rollback_turn(chat, &turn_id).await {
+      logs.push(logbus::LogLevel::Warn, "telegram", format!("rollback failed chat_id={chat}: {e}"));
+      let body = "Не вдалося прибрати попередню відповідь з історії — надсилаю виправлений текст як новий запит.";
+      if let Err(e) = tg_send_message(&client, &bot, chat, body, Some(message_id)).await {
+        log::info!("telegram: send rollback note failed: {e}");
+      }
+    }
+  }
+
+  let mut stream = match codex.start_turn_stream(chat, &prompt, &images).await {
+    Ok(s) => s,
+    Err(e) => {
+      let _ = typing_tx.send(true);
+      let msg = if e == "Busy" {
+        "Зачекай: обробляю попереднє повідомлення.".to_string()
+      } else if codex_runtime::is_sign_in_required(&e) {
+        let mut waiting = runtime.inner.awaiting_sign_in.lock().await;
+        let list = waiting.entry(chat).or_default();
+        if list.len() < SIGN_IN_RETRY_MAX {
+          list.push(QueuedPrompt::new(message_id, prompt, images));
+        }
+        "Codex потребує входу в акаунт. Власник бота може увійти командою /login — після входу запропоную повторити це повідомлення.".to_string()
+      } else {
+        format!("Codex error: {e}")
+      };
+      if let Err(e) = tg_send_message_series(&client, &bot, chat, &msg, Some(message_id)).await {
+        logs.push(logbus::LogLevel::Warn, "telegram", format!("sendMessageSeries failed: {e}"));
+      }
+      return;
+    }
+  };
+
+  logs.push(logbus::LogLevel::Info, "telegram", format!("codex stream started chat_id={chat}"));
+  remember_prompt(&runtime, chat, message_id, &stream.thread_id, &stream.turn_id).await;
+  // Messages carrying the answer, linked to the thread/turn once the turn ends.
+  let mut answer_ids: Vec<i64> = vec![];
+
+  let cfg = runtime.inner.config.read().await.clone();
+  let mode = chat_stream_mode(&runtime, &cfg, chat).await;
+  let mut live = LiveMessage::new(message_id);
+  let mut progress_rx = stream.progress_rx;
+  let mut progress_closed = false;
+  let mut live_dirty = false;
+  let mut next_edit = tokio::time::Instant::now();
+
+  let mut first_reply = true;
+  let mut sent_any = false;
+  // The first streamed chunk carries a Stop button; it is removed when the turn ends.
+  let mut stop_msg_id: Option<i64> = None;
+  let mut updates_closed = false;
+  let mut requests_closed = false;
+  let mut first_chunk_logged = false;
+  let mut done_rx = stream.done_rx;
+  loop {
+    tokio::select! {
+      maybe = stream.updates_rx.recv(), if !updates_closed => {
+        let Some(chunk) = maybe else {
+          updates_closed = true;
+          logs.push(logbus::LogLevel::Warn, "telegram", format!("codex updates channel closed chat_id={chat}"));
+          continue;
+        };
+        // Only the chunked presentation posts chunks; the other modes work from the full text.
+        if mode != StreamMode::Chunks {
+          continue;
+        }
+        let reply_to = if first_reply { Some(message_id) } else { None };
+        first_reply = false;
+        sent_any = true;
+        if !first_chunk_logged {
+          first_chunk_logged = true;
+          logs.push(logbus::LogLevel::Info, "telegram", format!("codex first chunk chat_id={chat}"));
+          match tg_send_rich_message(&client, &bot, chat, &chunk, reply_to, Some(stop_button_markup())).await {
+            Ok(id) => {
+              stop_msg_id = id;
+              answer_ids.extend(id);
+            }
+            Err(e) => logs.push(logbus::LogLevel::Warn, "telegram", format!("sendMessage failed: {e}")),
+          }
+          continue;
+        }
+        match tg_send_rich_message(&client, &bot, chat, &chunk, reply_to, None).await {
+          Ok(id) => answer_ids.extend(id),
+          Err(e) => logs.push(logbus::LogLevel::Warn, "telegram", format!("sendMessage failed: {e}")),
+        }
+      }
+      changed = progress_rx.changed(), if mode == StreamMode::Edit && !progress_closed => {
+        if changed.is_err() {
+          progress_closed = true;
+        } else {
+          live_dirty = true;
+        }
+      }
+      _ = tokio::time::sleep_until(next_edit), if live_dirty => {
+        live_dirty = false;
+        let text = progress_rx.borrow_and_update().clone();
+        if let Err(e) = live.show(&client, &bot, chat, &text, false).await {
+          logs.push(logbus::LogLevel::Warn, "telegram", format!("live edit failed: {e}"));
+        }
+        next_edit = tokio::time::Instant::now() + LIVE_EDIT_INTERVAL;
+      }
+      maybe = stream.requests_rx.recv(), if !requests_closed => {
+        let Some(req) = maybe else {
+          requests_closed = true;
+          continue;
+        };
+        match req {
+          CodexServerRequest::Approval(req) => {
+            let (body, markup) = approval_prompt(&req);
+            if let Err(e) = tg_send_message_markup(&client, &bot, chat, &body, Some(message_id), markup).await {
+              logs.push(logbus::LogLevel::Warn, "telegram", format!("send approval prompt failed: {e}"));
+            }
+          }
+          CodexServerRequest::UserInput(req) => {
+            {
+              let mut sessions = runtime.inner.input_sessions.lock().await;
+              sessions.insert(
+                chat,
+                UserInputSession {
+                  request_id: req.request_id,
+                  questions: req.questions,
+                  idx: 0,
+                  answers: HashMap::new(),
+                },
+              );
+            }
+            ask_next_question(&runtime, &client, &bot, chat).await;
+          }
+        }
+      }
+      done = &mut done_rx => {
+        // Stop typing loader.
+        let _ = typing_tx.send(true);
+        // Questions from this turn can't be answered anymore.
+        runtime.inner.input_sessions.lock().await.remove(&chat);
+        if let Some(id) = stop_msg_id.take() {
+          if let Err(e) = tg_edit_message_reply_markup(&client, &bot, chat, id, None).await {
+            log::info!("telegram: remove stop button failed: {e}");
+          }
+        }
+        if mode == StreamMode::Edit {
+          // Final redraw with the complete text (or whatever arrived before an error).
+          let text = match &done {
+            Ok(Ok(res)) => res.text.clone(),
+            _ => progress_rx.borrow().clone(),
+          };
+          if let Err(e) = live.show(&client, &bot, chat, &text, true).await {
+            logs.push(logbus::LogLevel::Warn, "telegram", format!("live edit failed: {e}"));
+          }
+          sent_any = live.started;
+          answer_ids.append(&mut live.sent);
+        }
+        // Drain any chunks that were queued before completion.
+        while let Ok(chunk) = stream.updates_rx.try_recv() {
+          if mode != StreamMode::Chunks {
+            continue;
+          }
+          let reply_to = if first_reply { Some(message_id) } else { None };
+          first_reply = false;
+          sent_any = true;
+          match tg_send_rich_message(&client, &bot, chat, &chunk, reply_to, None).await {
+            Ok(id) => answer_ids.extend(id),
+            Err(e) => logs.push(logbus::LogLevel::Warn, "telegram", format!("sendMessage failed: {e}")),
+          }
+        }
+
+        match done {
+          Ok(Ok(res)) if res.interrupted => {
+            logs.push(logbus::LogLevel::Info, "telegram", format!("codex done interrupted chat_id={chat} chars={}", res.text.chars().count()));
+            let msg = if sent_any || !res.text.trim().is_empty() {
+              if !sent_any {
+                match send_answer(&client, &bot, &cfg.telegram, chat, &res.text, Some(message_id)).await {
+                  Ok(ids) => answer_ids.extend(ids),
+                  Err(e) => logs.push(logbus::LogLevel::Warn, "telegram", format!("send answer failed: {e}")),
+                }
+              }
+              "Скасовано. Вище — частина відповіді, яку Codex встиг написати."
+            } else {
+              "Скасовано."
+            };
+            if let Err(e) = tg_send_message(&client, &bot, chat, msg, None).await {
+              logs.push(logbus::LogLevel::Warn, "telegram", format!("sendMessage failed: {e}"));
+            }
+          }
+          Ok(Ok(res)) => {
+            let final_text = res.text;
+            logs.push(logbus::LogLevel::Info, "telegram", format!("codex done ok chat_id={chat} chars={}", final_text.chars().count()));
+            if !sent_any {
+              if final_text.trim().is_empty() {
+                let msg = "Нема відповіді від Codex. Спробуй ще раз.".to_string();
+                if let Err(e) = tg_send_message(&client, &bot, chat, &msg, Some(message_id)).await {
+                  logs.push(logbus::LogLevel::Warn, "telegram", format!("sendMessage failed: {e}"));
+                }
+              } else {
+                match send_answer(&client, &bot, &cfg.telegram, chat, &final_text, Some(message_id)).await {
+                  Ok(ids) => answer_ids.extend(ids),
+                  Err(e) => logs.push(logbus::LogLevel::Warn, "telegram", format!("send answer failed: {e}")),
+                }
+              }
+            } else if needs_document(&cfg.telegram, &final_text) {
+              // Already shown as messages; add the file for reading it in one piece.
+              let files = answer_files(&cfg.telegram, &final_text);
+              match tg_send_files(&client, &bot, chat, files, Some("Повна відповідь одним файлом."), Some(message_id)).await {
+                Ok(ids) => answer_ids.extend(ids),
+                Err(e) => logs.push(logbus::LogLevel::Warn, "telegram", format!("send answer files failed: {e}")),
+              }
+            }
+          }
+          Ok(Err(e)) => {
+            logs.push(logbus::LogLevel::Warn, "telegram", format!("codex done err chat_id={chat}: {e}"));
+            let msg = format!("Codex error: {e}");
+            if let Err(e) = tg_send_message_series(&client, &bot, chat, &msg, Some(message_id)).await {
+              logs.push(logbus::LogLevel::Warn, "telegram", format!("sendMessageSeries failed: {e}"));
+            }
+          }
+          Err(_) => {
+            logs.push(logbus::LogLevel::Warn, "telegram", format!("codex done channel closed chat_id={chat}"));
+            if let Err(e) = tg_send_message_series(&client, &bot, chat, "Codex error: internal channel closed", Some(message_id)).await {
+              logs.push(logbus::LogLevel::Warn, "telegram", format!("sendMessageSeries failed: {e}"));
+            }
+          }
+        }
+        break;
+      }
+    }
+  }
+  remember_answer(&runtime, chat, &answer_ids, &stream.thread_id, &stream.turn_id).await;
+}
+
+// Final delivery of a whole answer: the message series, or above the `document_*` thresholds a
+// short summary plus the answer as files. Returns the ids of the messages sent.
+async fn send_answer(
+  client: &Client,
+  bot: &TgBot,
+  cfg: &TelegramConfig,
+  chat: ChatKey,
+  text: &str,
+  reply_to_message_id: Option<i64>,
+) -> Result<Vec<i64>, String> {
+  if !needs_document(cfg, text) {
+    return tg_send_rich_series(client, bot, chat, text, reply_to_message_id).await;
+  }
+  let files = answer_files(cfg, text);
+  let mut body = longform::summary(text, SUMMARY_MAX_CHARS);
+  if !body.is_empty() {
+    body.push_str("\n\n");
+  }
+  body.push_str(&format!(
+    "Відповідь довга ({} символів), повністю — у файлі {ANSWER_FILE_NAME}.",
+    text.chars().count()
+  ));
+  if files.len() > 1 {
+    let names: Vec<&str> = files[1..].iter().map(|f| f.name.as_str()).collect();
+    body.push_str(&format!("\nВеликі блоки коду — окремими файлами: {}.", names.join(", ")));
+  }
+  let mut ids: Vec<i64> = tg_send_rich_message(client, bot, chat, &body, reply_to_message_id, None)
+    .await?
+    .into_iter()
+    .collect();
+  ids.extend(tg_send_files(client, bot, chat, files, None, None).await?);
+  Ok(ids)
+}
+
+fn needs_document(cfg: &TelegramConfig, text: &str) -> bool {
+  let over_chars = cfg.document_min_chars > 0 && text.chars().count() > cfg.document_min_chars;
+  let over_messages = cfg.document_min_messages > 0
+    && split_for_telegram(text, SERIES_MAX_CHARS).iter().filter(|p| !p.trim().is_empty()).count()
+      > cfg.document_min_messages;
+  over_chars || over_messages
+}
+
+// The answer as Markdown, then its big code blocks.
+fn answer_files(cfg: &TelegramConfig, text: &str) -> Vec<Document> {
+  let mut files = vec![Document {
+    name: ANSWER_FILE_NAME.to_string(),
+    body: text.to_string(),
+  }];
+  files.extend(longform::code_files(text, cfg.code_file_min_chars));
+  files
+}
+
+// `caption` goes on the first file only.
+async fn tg_send_files(
+  client: &Client,
+  bot: &TgBot,
+  chat: ChatKey,
+  files: Vec<Document>,
+  caption: Option<&str>,
+  reply_to_message_id: Option<i64>,
+) -> Result<Vec<i64>, String> {
+  let mut ids = vec![];
+  for (i, f) in files.into_iter().enumerate() {
+    let caption = if i == 0 { caption } else { None };
+    ids.push(tg_send_document(client, bot, chat, &f.name, f.body.into_bytes(), caption, reply_to_message_id).await?);
+  }
+  Ok(ids)
+}
+
+// `<n>` = row of the last /threads page, anything else = thread id, nothing = the chat's thread.
+async fn resolve_thread_arg(runtime: &TelegramRuntime, chat: ChatKey, arg: Option<&str>) -> Option<String> {
+  let raw = arg.map(str::trim).unwrap_or("");
+  if raw.is_empty() {
+    return runtime.inner.codex.get_chat_thread(chat).await;
+  }
+  if let Ok(n) = raw.parse::<usize>() {
+    let pickers = runtime.inner.thread_pickers.lock().await;
+    return pickers
+      .get(&chat)
+      .and_then(|p| p.threads.get(n.saturating_sub(1)))
+      .map(|(id, _)| id.clone());
+  }
+  Some(raw.to_string())
+}
+
+// /new, /rename, /archive, /unarchive, /fork; returns the reply text.
+async fn thread_command(runtime: &TelegramRuntime, chat: ChatKey, cmd: &str, rest: Option<&str>) -> String {
+  let codex = &runtime.inner.codex;
+  let res = match cmd {
+    "/new" => codex
+      .start_new_thread_for_chat(chat)
+      .await
+      .map(|_| "Новий діалог розпочато. Попередній доступний у /threads.".to_string()),
+    "/rename" => {
+      let name = rest.map(str::trim).unwrap_or("");
+      match codex.get_chat_thread(chat).await {
+        _ if name.is_empty() => Ok("Напиши: /rename <назва>".to_string()),
+        None => Ok("Немає активного діалогу.".to_string()),
+        Some(id) => codex.set_thread_name(&id, name).await.map(|_| format!("Діалог перейменовано: {name}")),
+      }
+    }
+    "/archive" => match resolve_thread_arg(runtime, chat, rest).await {
+      None => Ok("Немає активного діалогу.".to_string()),
+      Some(id) => {
+        let was_current = codex.get_chat_thread(chat).await.as_deref() == Some(id.as_str());
+        codex.set_thread_archived(&id, true).await.map(|_| {
+          if was_current {
+            "Діалог заархівовано. Наступне повідомлення почне новий.".to_string()
+          } else {
+            "Діалог заархівовано.".to_string()
+          }
+        })
+      }
+    },
+    "/unarchive" => {
+      // No default here: the chat's own thread is never archived.
+      let id = match rest.map(str::trim).filter(|r| !r.is_empty()) {
+        Some(arg) => resolve_thread_arg(runtime, chat, Some(arg)).await,
+        None => None,
+      };
+      match id {
+        Some(id) => codex
+          .set_thread_archived(&id, false)
+          .await
+          .map(|_| format!("Діалог розархівовано. Підключити: /thread {id}")),
+        None => Ok("Напиши: /unarchive <номер або id> (номер — з /threads, фільтр «Архів»)".to_string()),
+      }
+    }
+    "/fork" => match codex.get_chat_thread(chat).await {
+      None => Ok("Немає активного діалогу.".to_string()),
+      Some(id) => match codex.fork_thread(&id, Some(chat)).await {
+        Ok(new_id) => codex
+          .attach_chat_to_thread(chat, new_id)
+          .await
+          .map(|_| "Створив копію діалогу й переключився на неї. Оригінал лишився без змін.".to_string()),
+        Err(e) => Err(e),
+      },
+    },
+    _ => Ok(String::new()),
+  };
+  res.unwrap_or_else(|e| format!("Codex error: {e}"))
+}
+
+const THREAD_PAGE_SIZE: u32 = 8;
+
+// Fetches one page of threads for the picker and remembers it for the chat.
+async fn load_thread_page(
+  runtime: &TelegramRuntime,
+  chat: ChatKey,
+  filter: ThreadFilter,
+  cursors: Vec<Option<String>>,
+  note: Option<String>,
+) -> (String, Option<serde_json::Value>) {
+  let archived = match filter {
+    ThreadFilter::Active => Some(false),
+    ThreadFilter::Archived => Some(true),
+    ThreadFilter::All => None,
+  };
+  let cursor = cursors.last().cloned().flatten();
+  let res = match runtime.inner.codex.list_threads(THREAD_PAGE_SIZE, cursor, archived).await {
+    Ok(r) => r,
+    Err(e) => return (format!("Codex error: {e}"), None),
+  };
+  let threads = res
+    .threads
+    .iter()
+    .map(|th| {
+      let title = th
+        .title
+        .clone()
+        .or_else(|| th.preview.clone())
+        .unwrap_or_else(|| "Діалог".to_string());
+      (th.id.clone(), title)
+    })
+    .collect();
+  runtime.inner.thread_pickers.lock().await.insert(
+    chat,
+    ThreadPicker {
+      filter,
+      cursors,
+      next_cursor: res.next_cursor,
+      threads,
+    },
+  );
+  render_thread_page(runtime, chat, note).await
+}
+
+async fn render_thread_page(
+  runtime: &TelegramRuntime,
+  chat: ChatKey,
+  note: Option<String>,
+) -> (String, Option<serde_json::Value>) {
+  let current = runtime.inner.codex.get_chat_thread(chat).await;
+  let pickers = runtime.inner.thread_pickers.lock().await;
+  let Some(p) = pickers.get(&chat) else {
+    return ("Список застарів, виклич /threads ще раз.".to_string(), None);
+  };
+
+  let heading = match p.filter {
+    ThreadFilter::Active => "Діалоги",
+    ThreadFilter::Archived => "Архівні діалоги",
+    ThreadFilter::All => "Усі діалоги",
+  };
+  let mut body = format!("{heading}, сторінка {}:", p.cursors.len());
+  if p.threads.is_empty() {
+    body.push_str("\n\nНічого не знайдено.");
+  }
+  if let Some(note) = note {
+    body = format!("{note}\n\n{body}");
+  }
+
+  let mut rows: Vec<serde_json::Value> = vec![];
+  for (i, (id, title)) in p.threads.iter().enumerate() {
+    let mut label: String = title.lines().next().unwrap_or("").chars().take(48).collect();
+    if current.as_deref() == Some(id.as_str()) {
+      label = format!("✓ {label}");
+    }
+    rows.push(serde_json::json!([{ "text": label, "callback_data": format!("ts:{i}") }]));
+  }
+  let mut nav = vec![];
+  if p.cursors.len() > 1 {
+    nav.push(serde_json::json!({ "text": "« Назад", "callback_data": "tp:p" }));
+  }
+  if p.next_cursor.is_some() {
+    nav.push(serde_json::json!({ "text": "Далі »", "callback_data": "tp:n" }));
+  }
+  if !nav.is_empty() {
+    rows.push(serde_json::Value::Array(nav));
+  }
+  let filters = [
+    (ThreadFilter::Active, "Активні", "active"),
+    (ThreadFilter::Archived, "Архів", "archived"),
+    (ThreadFilter::All, "Усі", "all"),
+  ];
+  rows.push(serde_json::Value::Array(
+    filters
+      .iter()
+      .map(|(f, label, key)| {
+        let text = if *f == p.filter { format!("• {label}") } else { label.to_string() };
+        serde_json::json!({ "text": text, "callback_data": format!("tp:f:{key}") })
+      })
+      .collect(),
+  ));
+
+  (body, Some(serde_json::json!({ "inline_keyboard": rows })))
+}
+
+// /settings: the chat's effective Codex settings plus buttons for every value the owner allows
+// (callback "st:<field>:<value>", "-" = back to default).
+async fn settings_menu(runtime: &TelegramRuntime, chat: ChatKey) -> (String, serde_json::Value) {
+  let codex = &runtime.inner.codex;
+  let limits = codex.chat_limits().await;
+  let cur = codex.effective_chat_settings(Some(chat)).await;
+  let sandbox = cur.sandbox.unwrap_or_default();
+  let approval = cur.approval_policy.unwrap_or_default();
+
+  let body = format!(
+    "Налаштування Codex для цього чату:\nМодель: {}\nЗусилля: {}\nПісочниця: {}\nПідтвердження: {}",
+    cur.model.as_deref().unwrap_or("типова"),
+    cur.effort.map(effort_label).unwrap_or("типове"),
+    sandbox_label(sandbox),
+    approval_label(approval),
+  );
+
+  let button = |label: &str, selected: bool, data: String| {
+    let mark = if selected { "✓ " } else { "" };
+    serde_json::json!({ "text": format!("{mark}{label}"), "callback_data": data })
+  };
+  let mut rows: Vec<Vec<serde_json::Value>> = vec![];
+  if !limits.models.is_empty() {
+    let mut row = vec![button("Типова модель", cur.model.is_none(), "st:m:-".to_string())];
+    for (i, m) in limits.models.iter().enumerate() {
+      row.push(button(m, cur.model.as_deref() == Some(m.as_str()), format!("st:m:{i}")));
+    }
+    rows.extend(row.chunks(3).map(|c| c.to_vec()));
+  }
+  let mut row = vec![button("Типове", cur.effort.is_none(), "st:e:-".to_string())];
+  for e in [ReasoningEffort::Minimal, ReasoningEffort::Low, ReasoningEffort::Medium, ReasoningEffort::High] {
+    if e <= limits.max_effort {
+      row.push(button(effort_label(e), cur.effort == Some(e), format!("st:e:{}", setting_value(e))));
+    }
+  }
+  rows.push(row);
+  if limits.max_sandbox > SandboxMode::ReadOnly {
+    rows.push(
+      [SandboxMode::ReadOnly, SandboxMode::WorkspaceWrite]
+        .into_iter()
+        .filter(|s| *s <= limits.max_sandbox)
+        .map(|s| button(sandbox_label(s), s == sandbox, format!("st:s:{}", setting_value(s))))
+        .collect(),
+    );
+  }
+  if limits.approval_policies.len() > 1 {
+    rows.push(
+      limits
+        .approval_policies
+        .iter()
+        .map(|p| button(approval_label(*p), *p == approval, format!("st:a:{}", setting_value(*p))))
+        .collect(),
+    );
+  }
+  (body, serde_json::json!({ "inline_keyboard": rows }))
+}
+
+// Settings enums travel in callback data as their config (serde) names.
+fn setting_value<T: serde::Serialize>(v: T) -> String {
+  serde_json::to_value(v)
+    .ok()
+    .and_then(|v| v.as_str().map(str::to_string))
+    .unwrap_or_default()
+}
+
+fn parse_setting<T: serde::de::DeserializeOwned>(s: &str) -> Option<T> {
+  serde_json::from_value(serde_json::Value::String(s.to_string())).ok()
+}
+
+fn effort_label(e: ReasoningEffort) -> &'static str {
+  match e {
+    ReasoningEffort::Minimal => "мінімальне",
+    ReasoningEffort::Low => "низьке",
+    ReasoningEffort::Medium => "середнє",
+    ReasoningEffort::High => "високе",
+  }
+}
+
+fn sandbox_label(s: SandboxMode) -> &'static str {
+  match s {
+    SandboxMode::ReadOnly => "лише читання",
+    SandboxMode::WorkspaceWrite => "запис у робочу папку",
+  }
+}
+
+fn approval_label(p: ApprovalPolicy) -> &'static str {
+  match p {
+    ApprovalPolicy::Never => "не питати",
+    ApprovalPolicy::OnRequest => "коли Codex попросить",
+    ApprovalPolicy::Untrusted => "для неперевірених команд",
+  }
+}
+
+// Why `set_chat_settings` refused a value (its own errors are English, for the logs).
+fn settings_refused_text(field: &str) -> &'static str {
+  match field {
+    "m" => "Ця модель не дозволена для цього чату.",
+    "e" => "Такий рівень міркувань вищий за дозволений.",
+    "s" => "Такий режим пісочниці вищий за дозволений.",
+    _ => "Така політика підтверджень не дозволена.",
+  }
+}
+
+// `/workspace <name>`; "-" or "default" unbinds the chat.
+async fn bind_workspace(runtime: &TelegramRuntime, chat: ChatKey, arg: &str) -> String {
+  let codex = &runtime.inner.codex;
+  let name = match arg.trim() {
+    "-" | "default" => None,
+    n => Some(n.to_string()),
+  };
+  match codex.set_chat_workspace(chat, name).await {
+    Ok(()) => {
+      runtime
+        .inner
+        .logs
+        .push(logbus::LogLevel::Info, "telegram", format!("workspace set chat_id={chat} workspace={arg}"));
+      match codex.chat_workspace(chat).await {
+        Some(ws) => format!(
+          "Робоча папка: {} ({})\nНаступне повідомлення почне новий діалог.",
+          ws.name, ws.path
+        ),
+        None => "Робоча папка: типова.\nНаступне повідомлення почне новий діалог.".to_string(),
+      }
+    }
+    Err(e) => format!("Не вдалося змінити робочу папку: {e}"),
+  }
+}
+
+async fn workspace_menu(runtime: &TelegramRuntime, chat: ChatKey) -> (String, Option<serde_json::Value>) {
+  let codex = &runtime.inner.codex;
+  let list = codex.workspaces().await;
+  if list.is_empty() {
+    return ("Робочі папки не налаштовані (Налаштування → Codex).".to_string(), None);
+  }
+  let current = codex.chat_workspace(chat).await.map(|w| w.name);
+  let mut body = format!(
+    "Робоча папка: {}\n\nОбрати: /workspace <назва>",
+    current.as_deref().unwrap_or("типова")
+  );
+  for w in &list {
+    body.push_str(&format!("\n• {} — {}", w.name, w.path));
+  }
+  let mut rows: Vec<serde_json::Value> = list
+    .iter()
+    .enumerate()
+    .map(|(i, w)| {
+      let mark = if current.as_deref() == Some(w.name.as_str()) { "✓ " } else { "" };
+      serde_json::json!([{ "text": format!("{mark}{}", w.name), "callback_data": format!("ws:{i}") }])
+    })
+    .collect();
+  let mark = if current.is_none() { "✓ " } else { "" };
+  rows.push(serde_json::json!([{ "text": format!("{mark}Типова"), "callback_data": "ws:-" }]));
+  (body, Some(serde_json::json!({ "inline_keyboard": rows })))
+}
+
+fn parse_stream_mode(s: &str) -> Option<StreamMode> {
+  match s {
+    "chunks" => Some(StreamMode::Chunks),
+    "edit" => Some(StreamMode::Edit),
+    "final" => Some(StreamMode::Final),
+    _ => None,
+  }
+}
+
+fn stream_mode_label(mode: StreamMode) -> &'static str {
+  match mode {
+    StreamMode::Chunks => "частинами (нове повідомлення кожні кілька речень)",
+    StreamMode::Edit => "одне повідомлення, що оновлюється",
+    StreamMode::Final => "лише готова відповідь",
+  }
+}
+
+fn stream_mode_markup() -> serde_json::Value {
+  serde_json::json!({
+    "inline_keyboard": [[
+      { "text": "Частинами", "callback_data": "sm:chunks" },
+      { "text": "Оновлювати", "callback_data": "sm:edit" },
+      { "text": "Лише фінал", "callback_data": "sm:final" }
+    ]]
+  })
+}
+
+async fn chat_stream_mode(runtime: &TelegramRuntime, cfg: &AppConfig, chat: ChatKey) -> StreamMode {
+  let prefs = runtime.inner.chat_prefs.lock().await;
+  // A forum topic without its own choice uses its group's.
+  prefs
+    .get(&chat)
+    .and_then(|p| p.stream_mode)
+    .or_else(|| prefs.get(&chat.whole_chat()).and_then(|p| p.stream_mode))
+    .unwrap_or(cfg.telegram.stream_mode)
+}
+
+async fn set_chat_stream_mode(runtime: &TelegramRuntime, chat: ChatKey, mode: StreamMode) -> Result<(), String> {
+  let app = runtime.inner.app.read().await.clone().ok_or_else(|| "app handle missing".to_string())?;
+  let mut prefs = runtime.inner.chat_prefs.lock().await;
+  prefs.entry(chat).or_default().stream_mode = Some(mode);
+  save_chat_prefs(&app, &prefs)
+}
+
+// Edit-mode presentation: one message redrawn as the answer grows. Near Telegram's size limit the
+// current message is frozen and the rest continues in a new one.
+struct LiveMessage {
+  reply_to: i64,
+  msg_id: Option<i64>,
+  // Char offset in the answer where the current message starts.
+  base: usize,
+  shown: String,
+  has_stop_button: bool,
+  started: bool,
+  // Every message posted so far.
+  sent: Vec<i64>,
+}
+
+impl LiveMessage {
+  fn new(reply_to: i64) -> Self {
+    Self {
+      reply_to,
+      msg_id: None,
+      base: 0,
+      shown: String::new(),
+      has_stop_button: false,
+      started: false,
+      sent: vec![],
+    }
+  }
+
+  // Redraws with the full answer so far; `finished` drops the Stop button. A code block split by a
+  // rollover is closed in one message and reopened (same language tag) in the next.
+  async fn show(&mut self, client: &Client, bot: &TgBot, chat: ChatKey, full: &str, finished: bool) -> Result<(), String> {
+    let fences = FenceMap::new(None, full);
+    loop {
+      let seg_start = full.char_indices().nth(self.base).map_or(full.len(), |(i, _)| i);
+      let seg = &full[seg_start..];
+      let cut = (seg.chars().count() > LIVE_MAX_CHARS).then(|| live_cut_point(seg, |b| fences.inside(seg_start + b)));
+      let seg_end = cut.map_or(seg.len(), |n| seg.char_indices().nth(n).map_or(seg.len(), |(i, _)| i));
+      let body = &seg[..seg_end];
+      let mut part = body.to_string();
+      if let Some(lang) = fences.open_at(seg_start) {
+        part = format!("```{lang}\n{part}");
+      }
+      if cut.is_some() && fences.inside(seg_start + seg_end) {
+        part = format!("{}\n```", part.trim_end_matches('\n'));
+      }
+      let stop = cut.is_none() && !finished;
+      let markup = stop.then(stop_button_markup);
+
+      if !body.trim().is_empty() {
+        match self.msg_id {
+          None => {
+            let reply_to = if self.started { None } else { Some(self.reply_to) };
+            self.msg_id = tg_send_rich_message(client, bot, chat, &part, reply_to, markup).await?;
+            self.sent.extend(self.msg_id);
+            self.started = true;
+          }
+          Some(id) if part != self.shown || self.has_stop_button != stop => {
+            tg_edit_rich_message(client, bot, chat, id, &part, markup).await?;
+          }
+          Some(_) => {}
+        }
+        self.shown = part;
+        self.has_stop_button = stop;
+      }
+
+      let Some(n) = cut else { return Ok(()) };
+      self.base += n;
+      self.msg_id = None;
+      self.shown.clear();
+    }
+  }
+}
+
+// Roll over at a line break in the second half of the window when there is one, preferring one
+// outside code blocks. `in_code` takes a byte offset into `seg`; returns a char count.
+fn live_cut_point(seg: &str, in_code: impl Fn(usize) -> bool) -> usize {
+  let chars: Vec<(usize, char)> = seg.char_indices().take(LIVE_MAX_CHARS).collect();
+  let breaks = || {
+    (LIVE_MAX_CHARS / 2..chars.len())
+      .rev()
+      .filter(|&i| chars[i].1 == '\n')
+      .map(|i| (i + 1, chars[i].0 + 1))
+  };
+  breaks()
+    .find(|&(_, byte)| !in_code(byte))
+    .or_else(|| breaks().next())
+    .map_or(chars.len(), |(n, _)| n)
+}
+
+fn stop_button_markup() -> serde_json::Value {
+  serde_json::json!({
+    "inline_keyboard": [[{ "text": "Стоп", "callback_data": "stop" }]]
+  })
+}
+
+async fn stop_codex_turn(runtime: &TelegramRuntime, chat: ChatKey) -> &'static str {
+  match runtime.inner.codex.interrupt_turn(chat).await {
+    Ok(true) => "Зупиняю…",
+    Ok(false) => "Зараз нічого не виконується.",
+    Err(e) => {
+      runtime
+        .inner
+        .logs
+        .push(logbus::LogLevel::Warn, "telegram", format!("interrupt failed chat_id={chat}: {e}"));
+      "Не вдалося зупинити."
+    }
+  }
+}
+
+fn approval_prompt(req: &CodexApprovalRequest) -> (String, serde_json::Value) {
+  let mut body = String::new();
+  if req.kind == "commandExecution" {
+    body.push_str("Codex хоче виконати команду:\n\n");
+    body.push_str(req.command.as_deref().unwrap_or("(команда невідома)"));
+    if let Some(cwd) = req.cwd.as_deref() {
+      body.push_str(&format!("\n\nПапка: {cwd}"));
+    }
+  } else {
+    body.push_str("Codex хоче змінити файли:\n\n");
+    body.push_str(req.changes.as_deref().unwrap_or("(деталі недоступні)"));
+  }
+  if let Some(reason) = req.reason.as_deref().filter(|r| !r.trim().is_empty()) {
+    body.push_str(&format!("\n\nПричина: {reason}"));
+  }
+
+  let id = req.request_id;
+  let markup = serde_json::json!({
+    "inline_keyboard": [
+      [
+        { "text": "Дозволити", "callback_data": format!("ap:{id}:a") },
+        { "text": "Відхилити", "callback_data": format!("ap:{id}:d") }
+      ],
+      [
+        { "text": "Дозволити до кінця сесії", "callback_data": format!("ap:{id}:s") }
+      ]
+    ]
+  });
+  (body, markup)
+}
+
+async fn handle_callback_query(
+  runtime: &TelegramRuntime,
+  client: &Client,
+  bot: &TgBot,
+  cfg: &AppConfig,
+  cb: &TgCallbackQuery,
+) {
+  let data = cb.data.clone().unwrap_or_default();
+  let Some(msg) = cb.message.as_ref() else {
+    let _ = tg_answer_callback_query(client, bot, &cb.id, None).await;
+    return;
+  };
+  let chat = msg.chat_key();
+  let required = access::callback_role(&data);
+  let role = access::role_for(&cfg.telegram, cb.from.as_ref().map(|u| u.id), chat.chat_id);
+  if role.is_none_or(|r| r < required) {
+    let body = denied_text(role, required);
+    let _ = tg_answer_callback_query(client, bot, &cb.id, Some(&body)).await;
+    return;
+  }
+
+  let parts: Vec<&str> = data.split(':').collect();
+  match parts.as_slice() {
+    ["ap", id, choice] => {
+      let (decision, label) = match *choice {
+        "a" => (CodexApprovalDecision::Accept, "Дозволено."),
+        "s" => (CodexApprovalDecision::AcceptForSession, "Дозволено до кінця сесії."),
+        _ => (CodexApprovalDecision::Decline, "Відхилено."),
+      };
+      let res = match id.parse::<u64>() {
+        Ok(id) => runtime.inner.codex.resolve_approval(chat, id, decision).await,
+        Err(_) => Err("bad request id".to_string()),
+      };
+      let status = match res {
+        Ok(_) => label.to_string(),
+        Err(e) => {
+          runtime
+            .inner
+            .logs
+            .push(logbus::LogLevel::Warn, "telegram", format!("approval answer failed chat_id={chat}: {e}"));
+          "Запит вже неактуальний.".to_string()
+        }
+      };
+      let _ = tg_answer_callback_query(client, bot, &cb.id, Some(&status)).await;
+      let text = format!("{}\n\n— {status}", msg.text.clone().unwrap_or_default().trim());
+      if let Err(e) = tg_edit_message_text(client, bot, chat, msg.message_id, &text, None).await {
+        log::info!("telegram: edit approval prompt failed: {e}");
+      }
+    }
+    ["sm", mode] => {
+      let status = match parse_stream_mode(mode) {
+        Some(mode) => match set_chat_stream_mode(runtime, chat, mode).await {
+          Ok(()) => format!("Режим відповіді: {}", stream_mode_label(mode)),
+          Err(e) => format!("Не вдалося зберегти: {e}"),
+        },
+        None => "Невідомий режим.".to_string(),
+      };
+      let _ = tg_answer_callback_query(client, bot, &cb.id, None).await;
+      if let Err(e) = tg_edit_message_text(client, bot, chat, msg.message_id, &status, None).await {
+        log::info!("telegram: edit /stream menu failed: {e}");
+      }
+    }
+    ["st", field, value] => {
+      let codex = &runtime.inner.codex;
+      let mut settings = codex.chat_settings(chat).await;
+      let reset = *value == "-";
+      let parsed = match *field {
+        "m" => {
+          let models = codex.chat_limits().await.models;
+          settings.model = if reset { None } else { value.parse::<usize>().ok().and_then(|i| models.get(i).cloned()) };
+          reset || settings.model.is_some()
+        }
+        "e" => {
+          settings.effort = if reset { None } else { parse_setting(value) };
+          reset || settings.effort.is_some()
+        }
+        "s" => {
+          settings.sandbox = parse_setting(value);
+          settings.sandbox.is_some()
+        }
+        "a" => {
+          settings.approval_policy = parse_setting(value);
+          settings.approval_policy.is_some()
+        }
+        _ => false,
+      };
+      let res = if parsed {
+        codex.set_chat_settings(chat, settings).await.map(|_| ()).map_err(|e| {
+          runtime
+            .inner
+            .logs
+            .push(logbus::LogLevel::Warn, "telegram", format!("settings refused chat_id={chat} {field}={value}: {e}"));
+          if codex_runtime::is_not_allowed(&e) {
+            settings_refused_text(field).to_string()
+          } else {
+            format!("Не вдалося зберегти налаштування: {e}")
+          }
+        })
+      } else {
+        Err("Список змінився. Виклич /settings ще раз.".to_string())
+      };
+      match res {
+        Ok(()) => {
+          let _ = tg_answer_callback_query(client, bot, &cb.id, Some("Збережено.")).await;
+          runtime
+            .inner
+            .logs
+            .push(logbus::LogLevel::Info, "telegram", format!("settings changed chat_id={chat} {field}={value}"));
+        }
+        Err(e) => {
+          let _ = tg_answer_callback_query(client, bot, &cb.id, Some(&e)).await;
+        }
+      }
+      let (body, markup) = settings_menu(runtime, chat).await;
+      if let Err(e) = tg_edit_message_text(client, bot, chat, msg.message_id, &body, Some(markup)).await {
+        log::info!("telegram: edit /settings menu failed: {e}");
+      }
+    }
+    ["rt", answer] => {
+      let _ = tg_answer_callback_query(client, bot, &cb.id, None).await;
+      let prompts = runtime.inner.awaiting_sign_in.lock().await.remove(&chat).unwrap_or_default();
+      let status = if *answer != "y" {
+        "Гаразд, не повторюю.".to_string()
+      } else if prompts.is_empty() {
+        "Нічого повторювати.".to_string()
+      } else {
+        format!("Повторюю ({}).", prompts.len())
+      };
+      if let Err(e) = tg_edit_message_text(client, bot, chat, msg.message_id, &status, None).await {
+        log::info!("telegram: edit retry offer failed: {e}");
+      }
+      if *answer == "y" {
+        for p in prompts {
+          runtime.inner.logs.push(logbus::LogLevel::Info, "telegram", format!("codex retry chat_id={chat}"));
+          submit_codex_prompt(runtime, client, bot, cfg, chat, p).await;
+        }
+      }
+    }
+    ["re", answer, id] => {
+      let _ = tg_answer_callback_query(client, bot, &cb.id, None).await;
+      let message_id = id.parse::<i64>().unwrap_or(0);
+      let prompt = runtime.inner.pending_reruns.lock().await.remove(&(chat, message_id));
+      let link = prompt_link(runtime, chat, message_id).await;
+      let required = prompt_role(runtime, chat).await;
+      let (status, rerun) = match (*answer, prompt, link) {
+        ("y", _, _) if role.is_none_or(|r| r < required) => (denied_text(role, required), None),
+        ("y", Some(prompt), Some(link)) => (
+          "Перезапускаю з новим текстом.".to_string(),
+          Some(QueuedPrompt {
+            message_id,
+            prompt,
+            images: vec![],
+            rollback_turn: Some(link.turn_id),
+          }),
+        ),
+        ("y", _, _) => ("Запит уже неактуальний.".to_string(), None),
+        _ => ("Гаразд, залишаю як є.".to_string(), None),
+      };
+      if let Err(e) = tg_edit_message_text(client, bot, chat, msg.message_id, &status, None).await {
+        log::info!("telegram: edit re-run offer failed: {e}");
+      }
+      if let Some(rerun) = rerun {
+        runtime.inner.logs.push(logbus::LogLevel::Info, "telegram", format!("codex re-run chat_id={chat}"));
+        submit_codex_prompt(runtime, client, bot, cfg, chat, rerun).await;
+      }
+    }
+    ["ws", idx] => {
+      let _ = tg_answer_callback_query(client, bot, &cb.id, None).await;
+      let name = match *idx {
+        "-" => Some("-".to_string()),
+        idx => {
+          let list = runtime.inner.codex.workspaces().await;
+          idx.parse::<usize>().ok().and_then(|i| list.get(i)).map(|w| w.name.clone())
+        }
+      };
+      let body = match name {
+        Some(name) => bind_workspace(runtime, chat, &name).await,
+        None => "Список змінився. Виклич /workspace ще раз.".to_string(),
+      };
+      if let Err(e) = tg_edit_message_text(client, bot, chat, msg.message_id, &body, None).await {
+        log::info!("telegram: edit /workspace menu failed: {e}");
+      }
+    }
+    ["tp", action @ ..] => {
+      let _ = tg_answer_callback_query(client, bot, &cb.id, None).await;
+      let state = {
+        let pickers = runtime.inner.thread_pickers.lock().await;
+        pickers.get(&chat).map(|p| (p.filter, p.cursors.clone(), p.next_cursor.clone()))
+      };
+      let (filter, mut cursors, next_cursor) = state.unwrap_or((ThreadFilter::Active, vec![None], None));
+      let filter = match action {
+        ["n"] => {
+          if next_cursor.is_some() {
+            cursors.push(next_cursor);
+          }
+          filter
+        }
+        ["p"] => {
+          if cursors.len() > 1 {
+            cursors.pop();
+          }
+          filter
+        }
+        ["f", f] => {
+          cursors = vec![None];
+          match *f {
+            "archived" => ThreadFilter::Archived,
+            "all" => ThreadFilter::All,
+            _ => ThreadFilter::Active,
+          }
+        }
+        _ => filter,
+      };
+      let (body, markup) = load_thread_page(runtime, chat, filter, cursors, None).await;
+      if let Err(e) = tg_edit_message_text(client, bot, chat, msg.message_id, &body, markup).await {
+        log::info!("telegram: edit thread picker failed: {e}");
+      }
+    }
+    ["ts", idx] => {
+      let picked = {
+        let pickers = runtime.inner.thread_pickers.lock().await;
+        idx
+          .parse::<usize>()
+          .ok()
+          .and_then(|i| pickers.get(&chat).and_then(|p| p.threads.get(i).cloned()))
+      };
+      let Some((thread_id, title)) = picked else {
+        let _ = tg_answer_callback_query(client, bot, &cb.id, Some("Список застарів, виклич /threads ще раз.")).await;
+        return;
+      };
+      match runtime.inner.codex.attach_chat_to_thread(chat, thread_id).await {
+        Ok(_) => {
+          let _ = tg_answer_callback_query(client, bot, &cb.id, Some("Підключено.")).await;
+          let (body, markup) = render_thread_page(runtime, chat, Some(format!("Активний діалог: {title}"))).await;
+          if let Err(e) = tg_edit_message_text(client, bot, chat, msg.message_id, &body, markup).await {
+            log::info!("telegram: edit thread picker failed: {e}");
+          }
+        }
+        Err(e) => {
+          let status = format!("Codex error: {e}");
+          let _ = tg_answer_callback_query(client, bot, &cb.id, Some(&status)).await;
+        }
+      }
+    }
+    ["stop"] => {
+      let status = stop_codex_turn(runtime, chat).await;
+      let _ = tg_answer_callback_query(client, bot, &cb.id, Some(status)).await;
+    }
+    ["ui", request_id, q_idx, opt_idx] => {
+      let (Ok(request_id), Ok(q_idx), Ok(opt_idx)) =
+        (request_id.parse::<u64>(), q_idx.parse::<usize>(), opt_idx.parse::<usize>())
+      else {
+        let _ = tg_answer_callback_query(client, bot, &cb.id, None).await;
+        return;
+      };
+      let label = {
+        let sessions = runtime.inner.input_sessions.lock().await;
+        sessions
+          .get(&chat)
+          .filter(|s| s.request_id == request_id && s.idx == q_idx)
+          .and_then(|s| s.questions.get(q_idx))
+          .and_then(|q| q.options.get(opt_idx))
+          .map(|o| o.label.clone())
+      };
+      let Some(label) = label else {
+        let _ = tg_answer_callback_query(client, bot, &cb.id, Some("Питання вже неактуальне.")).await;
+        return;
+      };
+      let _ = tg_answer_callback_query(client, bot, &cb.id, None).await;
+      let text = format!("{}\n\n— {label}", msg.text.clone().unwrap_or_default().trim());
+      if let Err(e) = tg_edit_message_text(client, bot, chat, msg.message_id, &text, None).await {
+        log::info!("telegram: edit question failed: {e}");
+      }
+      record_question_answer(runtime, client, bot, chat, label).await;
+    }
+    _ => {
+      let _ = tg_answer_callback_query(client, bot, &cb.id, None).await;
+    }
+  }
+}
+
+// Sends the current question of the chat's input session, or submits the answers when all are collected.
+async fn ask_next_question(runtime: &TelegramRuntime, client: &Client, bot: &TgBot, chat: ChatKey) {
+  let next = {
+    let sessions = runtime.inner.input_sessions.lock().await;
+    let Some(s) = sessions.get(&chat) else { return; };
+    s.questions
+      .get(s.idx)
+      .cloned()
+      .map(|q| (s.request_id, s.idx, s.questions.len(), q))
+  };
+
+  let Some((request_id, idx, total, q)) = next else {
+    submit_question_answers(runtime, client, bot, chat).await;
+    return;
+  };
+
+  let mut body = String::from("Codex питає");
+  if total > 1 {
+    body.push_str(&format!(" ({}/{total})", idx + 1));
+  }
+  body.push_str(":\n\n");
+  if let Some(header) = q.header.as_deref().filter(|h| !h.trim().is_empty()) {
+    body.push_str(header.trim());
+    body.push('\n');
+  }
+  body.push_str(q.question.trim());
+
+  let res = if q.options.is_empty() {
+    body.push_str("\n\nНапиши відповідь повідомленням.");
+    tg_send_message_markup(client, bot, chat, &body, None, serde_json::json!({ "force_reply": true }))
+      .await
+  } else {
+    for o in &q.options {
+      if let Some(d) = o.description.as_deref().filter(|d| !d.trim().is_empty()) {
+        body.push_str(&format!("\n• {} — {}", o.label, d.trim()));
+      }
+    }
+    if q.is_other {
+      body.push_str("\n\nАбо напиши свою відповідь повідомленням.");
+    }
+    let rows: Vec<serde_json::Value> = q
+      .options
+      .iter()
+      .enumerate()
+      .map(|(i, o)| {
+        let label: String = o.label.chars().take(60).collect();
+        serde_json::json!([{ "text": label, "callback_data": format!("ui:{request_id}:{idx}:{i}") }])
+      })
+      .collect();
+    tg_send_message_markup(client, bot, chat, &body, None, serde_json::json!({ "inline_keyboard": rows })).await
+  };
+  if let Err(e) = res {
+    runtime
+      .inner
+      .logs
+      .push(logbus::LogLevel::Warn, "telegram", format!("send question failed: {e}"));
+  }
+}
+
+// Treats a plain text message as the answer to the chat's open question, if there is one.
+async fn answer_pending_question(
+  runtime: &TelegramRuntime,
+  client: &Client,
+  bot: &TgBot,
+  chat: ChatKey,
+  text: &str,
+) -> bool {
+  let accepts_text = {
+    let sessions = runtime.inner.input_sessions.lock().await;
+    sessions
+      .get(&chat)
+      .and_then(|s| s.questions.get(s.idx))
+      .map(|q| q.options.is_empty() || q.is_other)
+  };
+  match accepts_text {
+    None => false,
+    Some(false) => {
+      let msg = "Вибери один із варіантів кнопками вище.";
+      if let Err(e) = tg_send_message(client, bot, chat, msg, None).await {
+        log::info!("telegram: send question hint failed: {e}");
+      }
+      true
+    }
+    Some(true) => {
+      record_question_answer(runtime, client, bot, chat, text.to_string()).await;
+      true
+    }
+  }
+}
+
+async fn record_question_answer(runtime: &TelegramRuntime, client: &Client, bot: &TgBot, chat: ChatKey, answer: String) {
+  {
+    let mut sessions = runtime.inner.input_sessions.lock().await;
+    let Some(s) = sessions.get_mut(&chat) else { return; };
+    let Some(q) = s.questions.get(s.idx) else { return; };
+    s.answers.entry(q.id.clone()).or_default().push(answer);
+    s.idx += 1;
+  }
+  ask_next_question(runtime, client, bot, chat).await;
+}
+
+async fn submit_question_answers(runtime: &TelegramRuntime, client: &Client, bot: &TgBot, chat: ChatKey) {
+  let Some(s) = runtime.inner.input_sessions.lock().await.remove(&chat) else { return; };
+  let body = match runtime.inner.codex.answer_user_input(chat, s.request_id, s.answers).await {
+    Ok(_) => "Відповіді передано Codex.".to_string(),
+    Err(e) => {
+      runtime
+        .inner
+        .logs
+        .push(logbus::LogLevel::Warn, "telegram", format!("answer user input failed chat_id={chat}: {e}"));
+      "Питання вже неактуальне.".to_string()
+    }
+  };
+  if let Err(e) = tg_send_message(client, bot, chat, &body, None).await {
+    log::info!("telegram: send answers ack failed: {e}");
+  }
+}
+
+fn redact_token(s: &str, token: &str) -> String {
+  if token.trim().is_empty() {
+    return s.to_string();
+  }
+  s.replace(token, "[REDACTED]")
+}
+
+// Log lines leaving the app: the bot token plus anything shaped like a bot token
+// (`<digits>:<35 chars>`, also inside URLs) or an `sk-` API key.
+fn redact_secrets(s: &str, token: &str) -> String {
+  let s = redact_token(s, token);
+  let chars: Vec<char> = s.chars().collect();
+  let is_tok = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+  let mut out = String::with_capacity(s.len());
+  let mut i = 0usize;
+  while i < chars.len() {
+    if chars[i] == ':' {
+      let digits = out.chars().rev().take_while(|c| c.is_ascii_digit()).count();
+      let tail = chars[i + 1..].iter().take_while(|c| is_tok(**c)).count();
+      if digits >= 6 && tail >= 30 {
+        out.truncate(out.len() - digits);
+        out.push_str("[REDACTED]");
+        i += 1 + tail;
+        continue;
+      }
+    }
+    let at_word_start = i == 0 || !is_tok(chars[i - 1]);
+    if at_word_start && chars[i..].starts_with(&['s', 'k', '-']) {
+      let len = chars[i..].iter().take_while(|c| is_tok(**c)).count();
+      if len >= 23 {
+        out.push_str("[REDACTED]");
+        i += len;
+        continue;
+      }
+    }
+    out.push(chars[i]);
+    i += 1;
+  }
+  out
+}
+
+fn format_reqwest_error(e: &reqwest::Error, token: &str) -> String {
+  let mut parts: Vec<String> = vec![];
+
+  let mut base = e.to_string();
+  base = redact_token(&base, token);
+  parts.push(base);
+
+  if e.is_timeout() {
+    parts.push("timeout".to_string());
+  }
+  if e.is_connect() {
+    parts.push("connect".to_string());
+  }
+  if let Some(status) = e.status() {
+    parts.push(format!("http {status}"));
+  }
+
+  // Include a short error chain (often has the real cause).
+  let mut src = e.source();
+  let mut depth = 0usize;
+  while let Some(s) = src {
+    depth += 1;
+    if depth > 4 {
+      break;
+    }
+    let mut t = s.to_string();
+    t = redact_token(&t, token);
+    parts.push(t);
+    src = s.source();
+  }
+
+  parts.join(": ")
+}
+
+// Where Bot API calls go: the token plus the server base URL (official or self-hosted).
+#[derive(Debug, Clone)]
+pub(super) struct TgBot {
+  token: String,
+  api_base: String,
+  local: bool,
+}
+
+impl TgBot {
+  pub(super) fn new(token: String, cfg: &TelegramConfig) -> Self {
+    let mut api_base = cfg.api_base_url.trim().trim_end_matches('/').to_string();
+    if api_base.is_empty() {
+      api_base = config_store::default_api_base_url();
+    }
+    Self {
+      token,
+      api_base,
+      local: cfg.local_bot_api,
+    }
+  }
+
+  fn method_url(&self, method: &str) -> String {
+    format!("{}/bot{}/{method}", self.api_base, self.token)
+  }
+
+  // getFile limits: 20 MB on api.telegram.org, up to 2000 MB on a `--local` server.
+  fn max_download_bytes(&self) -> u64 {
+    if self.local {
+      2000 * 1024 * 1024
+    } else {
+      20 * 1024 * 1024
+    }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+struct TgResponse<T> {
+  ok: bool,
+  result: Option<T>,
+  description: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TgUser {
+  #[serde(default)]
+  id: i64,
+  #[serde(default)]
+  is_bot: bool,
+  #[serde(default)]
+  first_name: String,
+  last_name: Option<String>,
+  username: Option<String>,
+}
+
+pub(super) async fn tg_get_me(client: &Client, bot: &TgBot) -> Result<Option<String>, String> {
+  let url = bot.method_url("getMe");
+  let resp = client
+    .get(url)
+    .send()
+    .await
+    .map_err(|e| format!("getMe request failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let status = resp.status();
+  let raw = resp
+    .text()
+    .await
+    .map_err(|e| format!("getMe read failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let body: TgResponse<TgUser> = serde_json::from_str(&raw)
+    .map_err(|e| format!("getMe parse failed (http {status}): {e}"))?;
+  if !body.ok {
+    return Err(body.description.unwrap_or_else(|| "getMe failed".to_string()));
+  }
+  Ok(body.result.and_then(|u| u.username))
+}
+
+#[derive(Debug, Deserialize)]
+struct TgUpdate {
+  update_id: i64,
+  message: Option<TgMessage>,
+  edited_message: Option<TgMessage>,
+  callback_query: Option<TgCallbackQuery>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TgCallbackQuery {
+  id: String,
+  from: Option<TgUser>,
+  message: Option<TgMessage>,
+  data: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TgMessage {
+  message_id: i64,
+  chat: TgChat,
+  from: Option<TgUser>,
+  reply_to_message: Option<Box<TgMessage>>,
+  message_thread_id: Option<i64>,
+  is_topic_message: Option<bool>,
+  // The part of the replied-to message the user selected to quote.
+  quote: Option<TgTextQuote>,
+  text: Option<String>,
+  caption: Option<String>,
+  entities: Option<Vec<TgEntity>>,
+  caption_entities: Option<Vec<TgEntity>>,
+  photo: Option<Vec<TgPhotoSize>>,
+  document: Option<TgDocument>,
+  voice: Option<TgAudio>,
+  audio: Option<TgAudio>,
+}
+
+impl TgMessage {
+  // Forum topics are separate conversations. Outside forums `message_thread_id` only marks reply
+  // threads, which stay part of the chat.
+  fn chat_key(&self) -> ChatKey {
+    let topic = self.message_thread_id.filter(|_| self.is_topic_message == Some(true));
+    ChatKey::new(self.chat.id, topic)
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TgTextQuote {
+  text: String,
+}
+
+// `voice` and `audio` share the fields we need.
+#[derive(Debug, Clone, Deserialize)]
+struct TgAudio {
+  file_id: String,
+  file_name: Option<String>,
+  duration: Option<u64>,
+  file_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TgPhotoSize {
+  file_id: String,
+  file_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TgDocument {
+  file_id: String,
+  file_name: Option<String>,
+  mime_type: Option<String>,
+  file_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TgEntity {
+  #[serde(rename = "type")]
+  kind: String,
+  offset: usize,
+  length: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TgChat {
+  id: i64,
+  title: Option<String>,
+  #[serde(default, rename = "type")]
+  kind: String,
+}
+
+impl TgChat {
+  fn is_group(&self) -> bool {
+    self.kind == "group" || self.kind == "supergroup"
+  }
+}
+
+async fn tg_get_updates(
+  client: &Client,
+  bot: &TgBot,
+  offset: i64,
+  timeout_sec: i64,
+) -> Result<(i64, Vec<TgUpdate>), String> {
+  let url = bot.method_url("getUpdates");
+  // Use query params for maximum compatibility with the Telegram Bot API.
+  let resp = client
+    .get(url)
+    .query(&[("offset", offset), ("timeout", timeout_sec), ("limit", 50_i64)])
+    .query(&[("allowed_updates", serde_json::json!(ALLOWED_UPDATES).to_string())])
+    .send()
+    .await
+    .map_err(|e| format!("getUpdates request failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let status = resp.status();
+  let raw = resp
+    .text()
+    .await
+    .map_err(|e| format!("getUpdates read failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let body: TgResponse<Vec<TgUpdate>> = serde_json::from_str(&raw)
+    .map_err(|e| format!("getUpdates parse failed (http {status}): {e}"))?;
+  if !body.ok {
+    return Err(body.description.unwrap_or_else(|| "getUpdates failed".to_string()));
+  }
+  let items = body.result.unwrap_or_default();
+  let new_offset = items
+    .iter()
+    .map(|u| u.update_id + 1)
+    .max()
+    .unwrap_or(offset);
+  Ok((new_offset, items))
+}
+
+#[derive(Debug, Deserialize)]
+struct TgFile {
+  file_path: Option<String>,
+  file_size: Option<u64>,
+}
+
+// Downloads a file sent to the bot. A `--local` Bot API server returns an absolute path on its
+// own disk instead of a download path, so that case is read directly from the filesystem.
+// Saves the file to `dest`, streaming it rather than buffering it whole. A partial file is removed
+// on failure.
+async fn tg_download_file(
+  client: &Client,
+  bot: &TgBot,
+  file_id: &str,
+  max_bytes: u64,
+  dest: &Path,
+) -> Result<(), String> {
+  let url = bot.method_url("getFile");
+  let resp = client
+    .get(url)
+    .query(&[("file_id", file_id)])
+    .send()
+    .await
+    .map_err(|e| format!("getFile request failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let status = resp.status();
+  let raw = resp
+    .text()
+    .await
+    .map_err(|e| format!("getFile read failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let body: TgResponse<TgFile> = serde_json::from_str(&raw)
+    .map_err(|e| format!("getFile parse failed (http {status}): {e}"))?;
+  if !body.ok {
+    return Err(body.description.unwrap_or_else(|| "getFile failed".to_string()));
+  }
+  let file = body.result.ok_or_else(|| "getFile returned no file".to_string())?;
+  let limit = bot.max_download_bytes().min(max_bytes);
+  if file.file_size.unwrap_or(0) > limit {
+    return Err(format!("file is too big ({} bytes, limit {limit})", file.file_size.unwrap_or(0)));
+  }
+  let file_path = file.file_path.ok_or_else(|| "getFile returned no file_path".to_string())?;
+
+  if bot.local && Path::new(&file_path).is_absolute() {
+    tokio::fs::copy(&file_path, dest)
+      .await
+      .map_err(|e| format!("copy local file failed: {e}"))?;
+    return Ok(());
+  }
+
+  let url = format!("{}/file/bot{}/{file_path}", bot.api_base, bot.token);
+  let resp = client
+    .get(url)
+    .send()
+    .await
+    .map_err(|e| format!("file download failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  if !resp.status().is_success() {
+    return Err(format!("file download failed: http {}", resp.status()));
+  }
+  let res = write_download(resp, dest, limit, &bot.token).await;
+  if res.is_err() {
+    let _ = tokio::fs::remove_file(dest).await;
+  }
+  res
+}
+
+async fn write_download(mut resp: reqwest::Response, dest: &Path, limit: u64, token: &str) -> Result<(), String> {
+  let mut file = tokio::fs::File::create(dest)
+    .await
+    .map_err(|e| format!("create attachment file failed: {e}"))?;
+  let mut written = 0u64;
+  while let Some(chunk) = resp
+    .chunk()
+    .await
+    .map_err(|e| format!("file download read failed: {}", format_reqwest_error(&e, token)))?
+  {
+    written += chunk.len() as u64;
+    if written > limit {
+      return Err(format!("file is too big (over {limit} bytes)"));
+    }
+    file
+      .write_all(&chunk)
+      .await
+      .map_err(|e| format!("write attachment failed: {e}"))?;
+  }
+  file.flush().await.map_err(|e| format!("write attachment failed: {e}"))
+}
+
+async fn tg_set_webhook(client: &Client, bot: &TgBot, url: &str, secret: &str) -> Result<(), String> {
+  let api = bot.method_url("setWebhook");
+  let payload = serde_json::json!({
+    "url": url,
+    "secret_token": secret,
+    "allowed_updates": ALLOWED_UPDATES
+  });
+  let resp = client
+    .post(&api)
+    .json(&payload)
+    .send()
+    .await
+    .map_err(|e| format!("setWebhook request failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let status = resp.status();
+  let raw = resp
+    .text()
+    .await
+    .map_err(|e| format!("setWebhook read failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let body: TgResponse<serde_json::Value> = serde_json::from_str(&raw)
+    .map_err(|e| format!("setWebhook parse failed (http {status}): {e}"))?;
+  if !body.ok {
+    return Err(body.description.unwrap_or_else(|| "setWebhook failed".to_string()));
+  }
+  Ok(())
 }
 
-pub(super) async fn tg_get_me(client: &Client, token: &str) -> Result<Option<String>, String> {
-  let url = format!("https://api.telegram.org/bot{token}/getMe");
+async fn tg_delete_webhook(client: &Client, bot: &TgBot) -> Result<(), String> {
+  let url = bot.method_url("deleteWebhook");
   let resp = client
-    .get(url)
+    .post(&url)
+    .json(&serde_json::json!({ "drop_pending_updates": false }))
     .send()
     .await
-    .map_err(|e| format!("getMe request failed: {}", format_reqwest_error(&e, token)))?;
+    .map_err(|e| format!("deleteWebhook request failed: {}", format_reqwest_error(&e, &bot.token)))?;
   let status = resp.status();
   let raw = resp
     .text()
     .await
-    .map_err(|e| format!("getMe read failed: {}", format_reqwest_error(&e, token)))?;
-  let body: TgResponse<TgUser> = serde_json::from_str(&raw)
-    .map_err(|e| format!("getMe parse failed (http {status}): {e}"))?;
+    .map_err(|e| format!("deleteWebhook read failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let body: TgResponse<serde_json::Value> = serde_json::from_str(&raw)
+    .map_err(|e| format!("deleteWebhook parse failed (http {status}): {e}"))?;
   if !body.ok {
-    return Err(body.description.unwrap_or_else(|| "getMe failed".to_string()));
+    return Err(body.description.unwrap_or_else(|| "deleteWebhook failed".to_string()));
   }
-  Ok(body.result.and_then(|u| u.username))
-}
-
-#[derive(Debug, Deserialize)]
-struct TgUpdate {
-  update_id: i64,
-  message: Option<TgMessage>,
-}
-
-#[derive(Debug, Deserialize)]
-struct TgMessage {
-  message_id: i64,
-  chat: TgChat,
-  text: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-struct TgChat {
-  id: i64,
+  Ok(())
 }
 
-async fn tg_get_updates(
-  client: &Client,
-  token: &str,
-  offset: i64,
-  timeout_sec: i64,
-) -> Result<(i64, Vec<TgUpdate>), String> {
-  let url = format!("https://api.telegram.org/bot{token}/getUpdates");
-  // Use query params for maximum compatibility with the Telegram Bot API.
+pub(super) async fn tg_get_webhook_info(client: &Client, bot: &TgBot) -> Result<TelegramWebhookInfo, String> {
+  let url = bot.method_url("getWebhookInfo");
   let resp = client
     .get(url)
-    .query(&[("offset", offset), ("timeout", timeout_sec), ("limit", 50_i64)])
     .send()
     .await
-    .map_err(|e| format!("getUpdates request failed: {}", format_reqwest_error(&e, token)))?;
+    .map_err(|e| format!("getWebhookInfo request failed: {}", format_reqwest_error(&e, &bot.token)))?;
   let status = resp.status();
   let raw = resp
     .text()
     .await
-    .map_err(|e| format!("getUpdates read failed: {}", format_reqwest_error(&e, token)))?;
-  let body: TgResponse<Vec<TgUpdate>> = serde_json::from_str(&raw)
-    .map_err(|e| format!("getUpdates parse failed (http {status}): {e}"))?;
+    .map_err(|e| format!("getWebhookInfo read failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let body: TgResponse<TelegramWebhookInfo> = serde_json::from_str(&raw)
+    .map_err(|e| format!("getWebhookInfo parse failed (http {status}): {e}"))?;
   if !body.ok {
-    return Err(body.description.unwrap_or_else(|| "getUpdates failed".to_string()));
+    return Err(body.description.unwrap_or_else(|| "getWebhookInfo failed".to_string()));
   }
-  let items = body.result.unwrap_or_default();
-  let new_offset = items
-    .iter()
-    .map(|u| u.update_id + 1)
-    .max()
-    .unwrap_or(offset);
-  Ok((new_offset, items))
+  Ok(body.result.unwrap_or_default())
 }
 
 pub(super) async fn tg_send_message(
   client: &Client,
-  token: &str,
-  chat_id: i64,
+  bot: &TgBot,
+  chat: ChatKey,
   text: &str,
   reply_to_message_id: Option<i64>,
 ) -> Result<(), String> {
-  let url = format!("https://api.telegram.org/bot{token}/sendMessage");
+  let url = bot.method_url("sendMessage");
   let mut text = format_for_telegram(text);
   // Telegram limit is 4096 chars; chunk when needed.
   while !text.is_empty() {
@@ -611,7 +3542,10 @@ pub(super) async fn tg_send_message(
     text = text.chars().skip(4096).collect();
     // Don't send `reply_to_message_id: null` (Telegram can reject nulls on some fields).
     let mut payload = serde_json::Map::new();
-    payload.insert("chat_id".to_string(), serde_json::json!(chat_id));
+    payload.insert("chat_id".to_string(), serde_json::json!(chat.chat_id));
+    if let Some(topic) = chat.topic_id {
+      payload.insert("message_thread_id".to_string(), serde_json::json!(topic));
+    }
     payload.insert("text".to_string(), serde_json::json!(chunk));
     if let Some(reply_to_message_id) = reply_to_message_id {
       payload.insert("reply_to_message_id".to_string(), serde_json::json!(reply_to_message_id));
@@ -622,12 +3556,12 @@ pub(super) async fn tg_send_message(
       .json(&payload)
       .send()
       .await
-      .map_err(|e| format!("sendMessage request failed: {}", format_reqwest_error(&e, token)))?;
+      .map_err(|e| format!("sendMessage request failed: {}", format_reqwest_error(&e, &bot.token)))?;
     let status = resp.status();
     let raw = resp
       .text()
       .await
-      .map_err(|e| format!("sendMessage read failed: {}", format_reqwest_error(&e, token)))?;
+      .map_err(|e| format!("sendMessage read failed: {}", format_reqwest_error(&e, &bot.token)))?;
     let body: TgResponse<serde_json::Value> = serde_json::from_str(&raw)
       .map_err(|e| format!("sendMessage parse failed (http {status}): {e}"))?;
     if !body.ok {
@@ -637,28 +3571,315 @@ pub(super) async fn tg_send_message(
   Ok(())
 }
 
+// Sends `text` as-is (no formatting cleanup) with an inline keyboard; returns the new message id.
+async fn tg_send_message_markup(
+  client: &Client,
+  bot: &TgBot,
+  chat: ChatKey,
+  text: &str,
+  reply_to_message_id: Option<i64>,
+  reply_markup: serde_json::Value,
+) -> Result<i64, String> {
+  let text: String = text.chars().take(4096).collect();
+  tg_send_message_raw(client, bot, chat, &text, None, reply_to_message_id, Some(reply_markup)).await
+}
+
+// Sends Codex Markdown rendered as Telegram entities (split at 4096 chars); the keyboard goes on
+// the first message, whose id is returned. Parts Telegram rejects are re-sent as plain text.
+async fn tg_send_rich_message(
+  client: &Client,
+  bot: &TgBot,
+  chat: ChatKey,
+  text: &str,
+  reply_to_message_id: Option<i64>,
+  reply_markup: Option<serde_json::Value>,
+) -> Result<Option<i64>, String> {
+  let rendered = markdown::render(&clean_for_telegram(text));
+  let mut first_id: Option<i64> = None;
+  for part in rendered.split(4096) {
+    if part.text.trim().is_empty() {
+      continue;
+    }
+    let reply_to = if first_id.is_none() { reply_to_message_id } else { None };
+    let markup = if first_id.is_none() { reply_markup.clone() } else { None };
+    let entities = part.has_entities().then(|| part.entities_json());
+    let res = match tg_send_message_raw(client, bot, chat, &part.text, entities, reply_to, markup.clone()).await {
+      Err(e) if part.has_entities() => {
+        log::info!("telegram: formatted message rejected, sending plain text: {e}");
+        tg_send_message_raw(client, bot, chat, &part.text, None, reply_to, markup).await
+      }
+      other => other,
+    };
+    let id = res?;
+    first_id.get_or_insert(id);
+  }
+  Ok(first_id)
+}
+
+async fn tg_send_message_raw(
+  client: &Client,
+  bot: &TgBot,
+  chat: ChatKey,
+  text: &str,
+  entities: Option<serde_json::Value>,
+  reply_to_message_id: Option<i64>,
+  reply_markup: Option<serde_json::Value>,
+) -> Result<i64, String> {
+  let url = bot.method_url("sendMessage");
+  let mut payload = serde_json::Map::new();
+  payload.insert("chat_id".to_string(), serde_json::json!(chat.chat_id));
+  if let Some(topic) = chat.topic_id {
+    payload.insert("message_thread_id".to_string(), serde_json::json!(topic));
+  }
+  payload.insert("text".to_string(), serde_json::json!(text));
+  if let Some(entities) = entities {
+    payload.insert("entities".to_string(), entities);
+  }
+  if let Some(reply_to_message_id) = reply_to_message_id {
+    payload.insert("reply_to_message_id".to_string(), serde_json::json!(reply_to_message_id));
+  }
+  payload.insert("disable_web_page_preview".to_string(), serde_json::json!(true));
+  if let Some(reply_markup) = reply_markup {
+    payload.insert("reply_markup".to_string(), reply_markup);
+  }
+  let resp = client
+    .post(&url)
+    .json(&payload)
+    .send()
+    .await
+    .map_err(|e| format!("sendMessage request failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let status = resp.status();
+  let raw = resp
+    .text()
+    .await
+    .map_err(|e| format!("sendMessage read failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let body: TgResponse<serde_json::Value> = serde_json::from_str(&raw)
+    .map_err(|e| format!("sendMessage parse failed (http {status}): {e}"))?;
+  if !body.ok {
+    return Err(body.description.unwrap_or_else(|| "sendMessage failed".to_string()));
+  }
+  Ok(
+    body
+      .result
+      .and_then(|m| m.get("message_id").and_then(|v| v.as_i64()))
+      .unwrap_or(0),
+  )
+}
+
+async fn tg_send_document(
+  client: &Client,
+  bot: &TgBot,
+  chat: ChatKey,
+  file_name: &str,
+  bytes: Vec<u8>,
+  caption: Option<&str>,
+  reply_to_message_id: Option<i64>,
+) -> Result<i64, String> {
+  let url = bot.method_url("sendDocument");
+  let part = reqwest::multipart::Part::bytes(bytes).file_name(file_name.to_string());
+  let mut form = reqwest::multipart::Form::new()
+    .text("chat_id", chat.chat_id.to_string())
+    .part("document", part);
+  if let Some(topic) = chat.topic_id {
+    form = form.text("message_thread_id", topic.to_string());
+  }
+  if let Some(caption) = caption {
+    form = form.text("caption", caption.to_string());
+  }
+  if let Some(reply_to_message_id) = reply_to_message_id {
+    form = form.text("reply_to_message_id", reply_to_message_id.to_string());
+  }
+  let resp = client
+    .post(&url)
+    .multipart(form)
+    .send()
+    .await
+    .map_err(|e| format!("sendDocument request failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let status = resp.status();
+  let raw = resp
+    .text()
+    .await
+    .map_err(|e| format!("sendDocument read failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let body: TgResponse<serde_json::Value> = serde_json::from_str(&raw)
+    .map_err(|e| format!("sendDocument parse failed (http {status}): {e}"))?;
+  if !body.ok {
+    return Err(body.description.unwrap_or_else(|| "sendDocument failed".to_string()));
+  }
+  body
+    .result
+    .and_then(|m| m.get("message_id").and_then(|v| v.as_i64()))
+    .ok_or_else(|| "sendDocument response missing message_id".to_string())
+}
+
+async fn tg_edit_message_text(
+  client: &Client,
+  bot: &TgBot,
+  chat: ChatKey,
+  message_id: i64,
+  text: &str,
+  reply_markup: Option<serde_json::Value>,
+) -> Result<(), String> {
+  let text: String = text.chars().take(4096).collect();
+  tg_edit_message_raw(client, bot, chat, message_id, &text, None, reply_markup).await
+}
+
+// Edit counterpart of `tg_send_rich_message`; `text` must fit one message. An unchanged text
+// is not an error.
+async fn tg_edit_rich_message(
+  client: &Client,
+  bot: &TgBot,
+  chat: ChatKey,
+  message_id: i64,
+  text: &str,
+  reply_markup: Option<serde_json::Value>,
+) -> Result<(), String> {
+  let rendered = markdown::render(&clean_for_telegram(text));
+  let part = rendered.split(4096).into_iter().next().unwrap_or_default();
+  let entities = part.has_entities().then(|| part.entities_json());
+  let res = match tg_edit_message_raw(client, bot, chat, message_id, &part.text, entities, reply_markup.clone()).await {
+    Err(e) if part.has_entities() && !e.contains("message is not modified") => {
+      log::info!("telegram: formatted edit rejected, sending plain text: {e}");
+      tg_edit_message_raw(client, bot, chat, message_id, &part.text, None, reply_markup).await
+    }
+    other => other,
+  };
+  match res {
+    Err(e) if e.contains("message is not modified") => Ok(()),
+    other => other,
+  }
+}
+
+async fn tg_edit_message_raw(
+  client: &Client,
+  bot: &TgBot,
+  chat: ChatKey,
+  message_id: i64,
+  text: &str,
+  entities: Option<serde_json::Value>,
+  reply_markup: Option<serde_json::Value>,
+) -> Result<(), String> {
+  let url = bot.method_url("editMessageText");
+  let mut payload = serde_json::json!({
+    "chat_id": chat.chat_id,
+    "message_id": message_id,
+    "text": text,
+    "disable_web_page_preview": true
+  });
+  if let Some(entities) = entities {
+    payload["entities"] = entities;
+  }
+  if let Some(markup) = reply_markup {
+    payload["reply_markup"] = markup;
+  }
+  let resp = client
+    .post(&url)
+    .json(&payload)
+    .send()
+    .await
+    .map_err(|e| format!("editMessageText request failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let status = resp.status();
+  let raw = resp
+    .text()
+    .await
+    .map_err(|e| format!("editMessageText read failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let body: TgResponse<serde_json::Value> = serde_json::from_str(&raw)
+    .map_err(|e| format!("editMessageText parse failed (http {status}): {e}"))?;
+  if !body.ok {
+    return Err(body.description.unwrap_or_else(|| "editMessageText failed".to_string()));
+  }
+  Ok(())
+}
+
+async fn tg_edit_message_reply_markup(
+  client: &Client,
+  bot: &TgBot,
+  chat: ChatKey,
+  message_id: i64,
+  reply_markup: Option<serde_json::Value>,
+) -> Result<(), String> {
+  let url = bot.method_url("editMessageReplyMarkup");
+  let mut payload = serde_json::json!({
+    "chat_id": chat.chat_id,
+    "message_id": message_id
+  });
+  // Omitting reply_markup removes the inline keyboard.
+  if let Some(markup) = reply_markup {
+    payload["reply_markup"] = markup;
+  }
+  let resp = client
+    .post(&url)
+    .json(&payload)
+    .send()
+    .await
+    .map_err(|e| format!("editMessageReplyMarkup request failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let status = resp.status();
+  let raw = resp
+    .text()
+    .await
+    .map_err(|e| format!("editMessageReplyMarkup read failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let body: TgResponse<serde_json::Value> = serde_json::from_str(&raw)
+    .map_err(|e| format!("editMessageReplyMarkup parse failed (http {status}): {e}"))?;
+  if !body.ok {
+    return Err(body.description.unwrap_or_else(|| "editMessageReplyMarkup failed".to_string()));
+  }
+  Ok(())
+}
+
+async fn tg_answer_callback_query(
+  client: &Client,
+  bot: &TgBot,
+  callback_query_id: &str,
+  text: Option<&str>,
+) -> Result<(), String> {
+  let url = bot.method_url("answerCallbackQuery");
+  let mut payload = serde_json::json!({ "callback_query_id": callback_query_id });
+  if let Some(text) = text {
+    payload["text"] = serde_json::json!(text);
+  }
+  let resp = client
+    .post(&url)
+    .json(&payload)
+    .send()
+    .await
+    .map_err(|e| format!("answerCallbackQuery request failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let status = resp.status();
+  let raw = resp
+    .text()
+    .await
+    .map_err(|e| format!("answerCallbackQuery read failed: {}", format_reqwest_error(&e, &bot.token)))?;
+  let body: TgResponse<serde_json::Value> = serde_json::from_str(&raw)
+    .map_err(|e| format!("answerCallbackQuery parse failed (http {status}): {e}"))?;
+  if !body.ok {
+    return Err(body.description.unwrap_or_else(|| "answerCallbackQuery failed".to_string()));
+  }
+  Ok(())
+}
+
 async fn tg_send_chat_action(
   client: &Client,
-  token: &str,
-  chat_id: i64,
+  bot: &TgBot,
+  chat: ChatKey,
   action: &str,
 ) -> Result<(), String> {
-  let url = format!("https://api.telegram.org/bot{token}/sendChatAction");
-  let payload = serde_json::json!({
-    "chat_id": chat_id,
+  let url = bot.method_url("sendChatAction");
+  let mut payload = serde_json::json!({
+    "chat_id": chat.chat_id,
     "action": action
   });
+  if let Some(topic) = chat.topic_id {
+    payload["message_thread_id"] = serde_json::json!(topic);
+  }
   let resp = client
     .post(&url)
     .json(&payload)
     .send()
     .await
-    .map_err(|e| format!("sendChatAction request failed: {}", format_reqwest_error(&e, token)))?;
+    .map_err(|e| format!("sendChatAction request failed: {}", format_reqwest_error(&e, &bot.token)))?;
   let status = resp.status();
   let raw = resp
     .text()
     .await
-    .map_err(|e| format!("sendChatAction read failed: {}", format_reqwest_error(&e, token)))?;
+    .map_err(|e| format!("sendChatAction read failed: {}", format_reqwest_error(&e, &bot.token)))?;
   let body: TgResponse<serde_json::Value> = serde_json::from_str(&raw)
     .map_err(|e| format!("sendChatAction parse failed (http {status}): {e}"))?;
   if !body.ok {
@@ -667,14 +3888,34 @@ async fn tg_send_chat_action(
   Ok(())
 }
 
+// Like `tg_send_message_series`, but for Codex output: each part is rendered from Markdown.
+async fn tg_send_rich_series(
+  client: &Client,
+  bot: &TgBot,
+  chat: ChatKey,
+  text: &str,
+  reply_to_message_id: Option<i64>,
+) -> Result<Vec<i64>, String> {
+  let mut ids = vec![];
+  for part in split_for_telegram(text, SERIES_MAX_CHARS) {
+    if part.trim().is_empty() {
+      continue;
+    }
+    let reply_to = if ids.is_empty() { reply_to_message_id } else { None };
+    ids.extend(tg_send_rich_message(client, bot, chat, &part, reply_to, None).await?);
+    tokio::time::sleep(Duration::from_millis(220)).await;
+  }
+  Ok(ids)
+}
+
 async fn tg_send_message_series(
   client: &Client,
-  token: &str,
-  chat_id: i64,
+  bot: &TgBot,
+  chat: ChatKey,
   text: &str,
   reply_to_message_id: Option<i64>,
 ) -> Result<(), String> {
-  let parts = split_for_telegram(text, 900);
+  let parts = split_for_telegram(text, SERIES_MAX_CHARS);
   let mut first = true;
   for part in parts {
     if part.trim().is_empty() {
@@ -682,8 +3923,8 @@ async fn tg_send_message_series(
     }
     tg_send_message(
       client,
-      token,
-      chat_id,
+      bot,
+      chat,
       &part,
       if first { reply_to_message_id } else { None },
     )
@@ -697,33 +3938,36 @@ async fn tg_send_message_series(
 fn split_for_telegram(text: &str, max_chars: usize) -> Vec<String> {
   let mut out: Vec<String> = vec![];
   let mut s = text.replace("\r\n", "\n");
+  // Language tag of a code block cut by the previous part; it gets reopened in this one.
+  let mut open: Option<String> = None;
 
   // Preserve paragraph breaks, but keep messages short (a few sentences).
   while !s.trim().is_empty() {
+    // Code keeps its indentation; other text is trimmed as before.
+    let finish = |body: &str| match &open {
+      Some(lang) => format!("```{lang}\n{}", body.trim_end().trim_start_matches('\n')),
+      None => body.trim().to_string(),
+    };
+
     // Find the byte index that corresponds to max_chars.
-    let mut end_byte = s.len();
-    let mut count = 0usize;
-    for (i, _) in s.char_indices() {
-      if count == max_chars {
-        end_byte = i;
-        break;
-      }
-      count += 1;
-    }
+    let end_byte = s.char_indices().nth(max_chars).map(|(i, _)| i).unwrap_or(s.len());
 
     // Entire remainder fits in one message.
-    if count <= max_chars {
-      out.push(s.trim().to_string());
+    if end_byte == s.len() {
+      out.push(finish(&s));
       break;
     }
 
     let window = &s[..end_byte]; // safe UTF-8 boundary from char_indices
+    let fences = FenceMap::new(open.as_deref(), window);
+    let outside_fence = |cut: usize| !fences.inside(cut);
 
     // Prefer to cut on a blank line within the window.
-    if let Some(idx) = window.rfind("\n\n") {
+    if let Some((idx, _)) = window.rmatch_indices("\n\n").find(|(idx, _)| outside_fence(*idx)) {
       if idx > 0 {
-        out.push(window[..idx].trim().to_string());
+        out.push(finish(&window[..idx]));
         s = s[idx + 2..].to_string();
+        open = None;
         continue;
       }
     }
@@ -732,7 +3976,7 @@ fn split_for_telegram(text: &str, max_chars: usize) -> Vec<String> {
     let list_markers = ["\n- ", "\n• ", "\n* ", "\n1. ", "\n2. ", "\n3. ", "\n4. "];
     let mut list_cut: Option<usize> = None;
     for m in list_markers {
-      if let Some(idx) = window.rfind(m) {
+      if let Some((idx, _)) = window.rmatch_indices(m).find(|(idx, _)| outside_fence(idx + 1)) {
         if idx > 120 {
           list_cut = Some(list_cut.map(|b| b.max(idx + 1)).unwrap_or(idx + 1));
         }
@@ -746,7 +3990,7 @@ fn split_for_telegram(text: &str, max_chars: usize) -> Vec<String> {
       while let Some((i, ch)) = chars.next() {
         if ch == '.' || ch == '!' || ch == '?' || ch == '…' {
           let next = chars.peek().map(|(_, c)| *c);
-          if next.is_none() || next.map(|c| c.is_whitespace()).unwrap_or(false) {
+          if (next.is_none() || next.map(|c| c.is_whitespace()).unwrap_or(false)) && outside_fence(i + ch.len_utf8()) {
             ends.push(i + ch.len_utf8());
           }
         }
@@ -756,26 +4000,43 @@ fn split_for_telegram(text: &str, max_chars: usize) -> Vec<String> {
       if ends.len() >= desired && ends[desired - 1] > 80 {
         ends[desired - 1]
       } else {
-        // Fallback: last whitespace.
+        // Fallback: last whitespace, then (inside a long code block) the last line end.
         let mut last_ws = None;
         for (i, ch) in window.char_indices() {
-          if ch.is_whitespace() && i > 120 {
+          if ch.is_whitespace() && i > 120 && outside_fence(i) {
             last_ws = Some(i);
           }
         }
-        last_ws.unwrap_or(end_byte)
+        last_ws
+          .or_else(|| window.rfind('\n').filter(|&i| i > 0).map(|i| i + 1))
+          .unwrap_or(end_byte)
       }
     });
-    out.push(s[..cut].trim().to_string());
-    s = s[cut..].to_string();
+
+    // A forced cut inside a code block: close it here and reopen it with the same tag next time.
+    let left_open = fences.open_at(cut).map(str::to_string);
+    let mut part = finish(&s[..cut]);
+    if left_open.is_some() {
+      part.push_str("\n```");
+      // Keep the next line's indentation: it is part of the code.
+      s = s[cut..].trim_start_matches('\n').to_string();
+    } else {
+      s = s[cut..].trim_start().to_string();
+    }
+    out.push(part);
+    open = left_open;
   }
 
   out
 }
 
+// Plain-text variant for messages sent without entities: backticks would show up literally.
 fn format_for_telegram(input: &str) -> String {
+  clean_for_telegram(&input.replace('`', ""))
+}
+
+fn clean_for_telegram(input: &str) -> String {
   // Keep Telegram output readable:
-  // - remove inline backticks (Telegram doesn't render them as code without parse_mode)
   // - drop noisy absolute paths in skill listings
   // - collapse excessive blank lines
   let mut s = input.replace("\r\n", "\n");
@@ -788,9 +4049,6 @@ fn format_for_telegram(input: &str) -> String {
     }
   }
 
-  // Generic cleanup.
-  s = s.replace('`', "");
-
   // Remove "Файл: /abs/path" fragments to avoid giant wrapped lines.
   s = strip_file_paths(&s);
 
@@ -906,10 +4164,100 @@ fn collapse_blank_lines(s: &str, max_run: usize) -> String {
   out.trim().to_string()
 }
 
-fn extract_text_message(update: &TgUpdate) -> Option<(i64, i64, String)> {
+fn extract_text_message(update: &TgUpdate) -> Option<(ChatKey, i64, String)> {
   let msg = update.message.as_ref()?;
   let text = msg.text.as_ref()?.to_string();
-  Some((msg.chat.id, msg.message_id, text))
+  Some((msg.chat_key(), msg.message_id, text))
+}
+
+// Group messages the bot answers, mirroring what Telegram delivers with privacy mode on: commands
+// (bare or addressed to this bot, not `/cmd@other_bot`), replies to the bot's messages and
+// @mentions of the bot. With privacy mode off everything else arrives too and is ignored here.
+fn addressed_to_bot(m: &TgMessage, username: Option<&str>) -> bool {
+  let (text, entities) = match m.text.as_deref() {
+    Some(t) => (t, m.entities.as_deref()),
+    None => (m.caption.as_deref().unwrap_or(""), m.caption_entities.as_deref()),
+  };
+
+  let first = text.split_whitespace().next().unwrap_or("");
+  if first.starts_with('/') {
+    return match first.split_once('@') {
+      None => true,
+      Some((_, to)) => username.is_some_and(|u| u.eq_ignore_ascii_case(to)),
+    };
+  }
+
+  let Some(username) = username else {
+    return false;
+  };
+  let reply_to_bot = m
+    .reply_to_message
+    .as_ref()
+    .and_then(|r| r.from.as_ref())
+    .is_some_and(|u| u.is_bot && u.username.as_deref().is_some_and(|n| n.eq_ignore_ascii_case(username)));
+  if reply_to_bot {
+    return true;
+  }
+  let utf16: Vec<u16> = text.encode_utf16().collect();
+  entities.unwrap_or(&[]).iter().filter(|e| e.kind == "mention").any(|e| {
+    let end = (e.offset + e.length).min(utf16.len());
+    let mention = String::from_utf16_lossy(&utf16[e.offset.min(end)..end]);
+    mention.strip_prefix('@').is_some_and(|n| n.eq_ignore_ascii_case(username))
+  })
+}
+
+// Drops "@bot" from the text/caption so Codex only sees the actual request.
+fn strip_bot_mention(m: &mut TgMessage, username: &str) {
+  let strip = |s: &str| -> String {
+    let needle = format!("@{}", username.to_ascii_lowercase());
+    s.split(' ')
+      .filter(|w| w.to_ascii_lowercase().trim_end_matches(|c: char| !c.is_alphanumeric() && c != '_') != needle)
+      .collect::<Vec<_>>()
+      .join(" ")
+      .trim()
+      .to_string()
+  };
+  // Commands keep their `/cmd@bot` form; parse_command handles it.
+  if let Some(t) = m.text.as_deref().filter(|t| !t.starts_with('/')) {
+    m.text = Some(strip(t));
+  }
+  if let Some(c) = m.caption.as_deref().filter(|c| !c.starts_with('/')) {
+    m.caption = Some(strip(c));
+  }
+}
+
+// Sender's display name in group chats, so Codex can tell speakers apart.
+fn group_author(m: &TgMessage) -> Option<String> {
+  if !m.chat.is_group() {
+    return None;
+  }
+  sender_label(m)
+}
+
+fn sender_label(m: &TgMessage) -> Option<String> {
+  let u = m.from.as_ref()?;
+  let name = user_display_name(u);
+  match u.username.as_deref() {
+    Some(nick) if name.is_empty() => Some(format!("@{nick}")),
+    Some(nick) => Some(format!("{name} (@{nick})")),
+    None if name.is_empty() => None,
+    None => Some(name),
+  }
+}
+
+fn user_display_name(u: &TgUser) -> String {
+  let first = u.first_name.trim();
+  match u.last_name.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+    Some(last) => format!("{first} {last}"),
+    None => first.to_string(),
+  }
+}
+
+fn with_author(author: Option<&str>, prompt: &str) -> String {
+  match author {
+    Some(a) => format!("[{a}]: {prompt}"),
+    None => prompt.to_string(),
+  }
 }
 
 fn parse_command(text: &str) -> (Option<String>, Option<String>) {
@@ -934,6 +4282,60 @@ fn load_bot_state(app: &AppHandle) -> Result<BotState, String> {
   serde_json::from_str(&raw).map_err(|e| format!("parse bot state failed: {e}"))
 }
 
+fn load_access_requests(app: &AppHandle) -> Result<Vec<TelegramAccessRequest>, String> {
+  let path = paths::telegram_access_requests_path(app)?;
+  if !path.exists() {
+    return Ok(Vec::new());
+  }
+  let raw = fs::read_to_string(path).map_err(|e| format!("read access requests failed: {e}"))?;
+  serde_json::from_str(&raw).map_err(|e| format!("parse access requests failed: {e}"))
+}
+
+fn save_access_requests(app: &AppHandle, all: &[TelegramAccessRequest]) -> Result<(), String> {
+  let path = paths::telegram_access_requests_path(app)?;
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent).map_err(|e| format!("create access requests dir failed: {e}"))?;
+  }
+  let raw = serde_json::to_string_pretty(all).map_err(|e| format!("serialize access requests failed: {e}"))?;
+  fs::write(path, raw).map_err(|e| format!("write access requests failed: {e}"))
+}
+
+fn load_answer_links(app: &AppHandle) -> Result<HashMap<ChatKey, Vec<TurnLink>>, String> {
+  let path = paths::telegram_answer_links_path(app)?;
+  if !path.exists() {
+    return Ok(HashMap::new());
+  }
+  let raw = fs::read_to_string(path).map_err(|e| format!("read answer links failed: {e}"))?;
+  serde_json::from_str(&raw).map_err(|e| format!("parse answer links failed: {e}"))
+}
+
+fn save_answer_links(app: &AppHandle, links: &HashMap<ChatKey, Vec<TurnLink>>) -> Result<(), String> {
+  let path = paths::telegram_answer_links_path(app)?;
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent).map_err(|e| format!("create answer links dir failed: {e}"))?;
+  }
+  let raw = serde_json::to_string(links).map_err(|e| format!("serialize answer links failed: {e}"))?;
+  fs::write(path, raw).map_err(|e| format!("write answer links failed: {e}"))
+}
+
+fn load_chat_prefs(app: &AppHandle) -> Result<HashMap<ChatKey, ChatPrefs>, String> {
+  let path = paths::telegram_chat_prefs_path(app)?;
+  if !path.exists() {
+    return Ok(HashMap::new());
+  }
+  let raw = fs::read_to_string(path).map_err(|e| format!("read chat prefs failed: {e}"))?;
+  serde_json::from_str(&raw).map_err(|e| format!("parse chat prefs failed: {e}"))
+}
+
+fn save_chat_prefs(app: &AppHandle, prefs: &HashMap<ChatKey, ChatPrefs>) -> Result<(), String> {
+  let path = paths::telegram_chat_prefs_path(app)?;
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent).map_err(|e| format!("create chat prefs dir failed: {e}"))?;
+  }
+  let raw = serde_json::to_string_pretty(prefs).map_err(|e| format!("serialize chat prefs failed: {e}"))?;
+  fs::write(path, raw).map_err(|e| format!("write chat prefs failed: {e}"))
+}
+
 fn save_bot_state(app: &AppHandle, state: &BotState) -> Result<(), String> {
   let path = paths::telegram_bot_state_path(app)?;
   if let Some(parent) = path.parent() {
@@ -6,13 +6,18 @@ use tauri::AppHandle;
 
 use crate::core::{config_store::AppConfig, secrets};
 
-use super::runtime::{tg_get_me, tg_send_message};
+use super::{
+  runtime::{tg_get_me, tg_get_webhook_info, tg_send_message, TgBot},
+  types::TelegramWebhookInfo,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TelegramSelfTestResult {
   pub ok: bool,
   pub bot_username: Option<String>,
   pub sent_test_message: bool,
+  // getWebhookInfo result; `url` is empty when no webhook is registered (polling mode).
+  pub webhook_info: Option<TelegramWebhookInfo>,
   pub error: Option<String>,
 }
 
@@ -23,32 +28,38 @@ pub async fn telegram_self_test(app: AppHandle, cfg: AppConfig) -> Result<Telegr
       ok: false,
       bot_username: None,
       sent_test_message: false,
+      webhook_info: None,
       error: Some("Telegram token missing".to_string()),
     });
   }
 
+  let bot = TgBot::new(token, &cfg.telegram);
+
   let client = Client::builder()
     .timeout(Duration::from_secs(20))
     .http1_only()
     .build()
     .map_err(|e| format!("reqwest client failed: {e}"))?;
 
-  let bot_username = match tg_get_me(&client, &token).await {
+  let bot_username = match tg_get_me(&client, &bot).await {
     Ok(u) => u,
     Err(e) => {
       return Ok(TelegramSelfTestResult {
         ok: false,
         bot_username: None,
         sent_test_message: false,
+        webhook_info: None,
         error: Some(e),
       });
     }
   };
 
+  let webhook_info = tg_get_webhook_info(&client, &bot).await.ok();
+
   let mut sent_test_message = false;
   if let Some(&chat_id) = cfg.telegram.allowed_chat_ids.first() {
     let body = "Test: OK";
-    if tg_send_message(&client, &token, chat_id, body, None).await.is_ok() {
+    if tg_send_message(&client, &bot, chat_id.into(), body, None).await.is_ok() {
       sent_test_message = true;
     }
   }
@@ -57,6 +68,7 @@ pub async fn telegram_self_test(app: AppHandle, cfg: AppConfig) -> Result<Telegr
     ok: true,
     bot_username,
     sent_test_message,
+    webhook_info,
     error: None,
   })
 }
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::core::config_store::StreamMode;
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct TelegramStatus {
   pub running: bool,
@@ -8,7 +10,77 @@ pub struct TelegramStatus {
   pub last_error: Option<String>,
 }
 
+// Per-chat settings changed from Telegram (persisted in telegram-chat-prefs.json).
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ChatPrefs {
+  // None = `TelegramConfig.stream_mode`.
+  #[serde(default)]
+  pub stream_mode: Option<StreamMode>,
+}
+
+// A Telegram message tied to the Codex turn it started or answered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TurnLink {
+  pub message_id: i64,
+  pub thread_id: String,
+  pub turn_id: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessRequestStatus {
+  #[default]
+  Pending,
+  Denied,
+}
+
+// Someone without access who wrote to the bot (persisted in telegram-access-requests.json, one
+// entry per chat and sender, so each member of a group is approved on their own).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelegramAccessRequest {
+  pub chat_id: i64,
+  // Group title; None for private chats.
+  #[serde(default)]
+  pub chat_title: Option<String>,
+  #[serde(default)]
+  pub user_id: Option<i64>,
+  #[serde(default)]
+  pub name: String,
+  #[serde(default)]
+  pub username: Option<String>,
+  pub first_seen_unix_ms: u128,
+  pub last_seen_unix_ms: u128,
+  pub message_count: u64,
+  // Text of the latest /request_access.
+  #[serde(default)]
+  pub note: Option<String>,
+  #[serde(default)]
+  pub status: AccessRequestStatus,
+}
+
+// One-time code for `/pair`, shown in the desktop app.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelegramPairingCode {
+  pub code: String,
+  pub expires_at_unix_ms: u128,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct BotState {
   pub offset: i64,
 }
+
+// Subset of Bot API `WebhookInfo`.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct TelegramWebhookInfo {
+  #[serde(default)]
+  pub url: String,
+  #[serde(default)]
+  pub pending_update_count: i64,
+  #[serde(default)]
+  pub last_error_date: Option<i64>,
+  #[serde(default)]
+  pub last_error_message: Option<String>,
+  #[serde(default)]
+  pub ip_address: Option<String>,
+}
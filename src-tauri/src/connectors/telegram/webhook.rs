@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::{TcpListener, TcpStream},
+  sync::mpsc,
+};
+
+use crate::core::logbus;
+
+// Telegram updates are small; anything bigger is not a Bot API request.
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+const MAX_BODY_BYTES: usize = 4 * 1024 * 1024;
+
+// Random value for `setWebhook(secret_token)`, from the OS CSPRNG. Allowed chars are [A-Za-z0-9_-],
+// so hex is fine.
+pub(super) fn random_secret() -> Result<String, String> {
+  let mut buf = [0u8; 32];
+  getrandom::getrandom(&mut buf).map_err(|e| format!("OS random source failed: {e}"))?;
+  Ok(buf.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+pub(super) async fn bind(listen: &str) -> Result<TcpListener, String> {
+  TcpListener::bind(listen.trim())
+    .await
+    .map_err(|e| format!("webhook listen on {listen} failed: {e}"))
+}
+
+// Accepts Bot API webhook calls and forwards the JSON bodies to `tx`.
+// Plain HTTP only: TLS is expected to be terminated by the reverse proxy in front of the hub.
+// Stops when the receiving side of `tx` is dropped.
+pub(super) async fn serve(listener: TcpListener, secret: String, tx: mpsc::UnboundedSender<Value>, logs: logbus::LogBus) {
+  loop {
+    let (stream, peer) = tokio::select! {
+      _ = tx.closed() => return,
+      res = listener.accept() => match res {
+        Ok(v) => v,
+        Err(e) => {
+          logs.push(logbus::LogLevel::Warn, "telegram", format!("webhook accept failed: {e}"));
+          tokio::time::sleep(Duration::from_millis(200)).await;
+          continue;
+        }
+      },
+    };
+
+    let secret = secret.clone();
+    let tx = tx.clone();
+    let logs = logs.clone();
+    tauri::async_runtime::spawn(async move {
+      let res = tokio::time::timeout(Duration::from_secs(15), handle_conn(stream, &secret, &tx)).await;
+      let err = match res {
+        Ok(Ok(())) => return,
+        Ok(Err(e)) => e,
+        Err(_) => "timeout".to_string(),
+      };
+      logs.push(logbus::LogLevel::Warn, "telegram", format!("webhook request from {peer} rejected: {err}"));
+    });
+  }
+}
+
+async fn handle_conn(mut stream: TcpStream, secret: &str, tx: &mpsc::UnboundedSender<Value>) -> Result<(), String> {
+  let (head, mut body) = read_head(&mut stream).await?;
+
+  let mut lines = head.split("\r\n");
+  let method = lines.next().unwrap_or("").split_whitespace().next().unwrap_or("").to_string();
+  let mut content_length: Option<usize> = None;
+  let mut secret_ok = false;
+  for line in lines {
+    let Some((k, v)) = line.split_once(':') else { continue; };
+    match k.trim().to_ascii_lowercase().as_str() {
+      "content-length" => content_length = v.trim().parse::<usize>().ok(),
+      "x-telegram-bot-api-secret-token" => secret_ok = constant_time_eq(v.trim().as_bytes(), secret.as_bytes()),
+      _ => {}
+    }
+  }
+
+  if method != "POST" {
+    write_response(&mut stream, "405 Method Not Allowed").await;
+    return Err(format!("method {method}"));
+  }
+  if !secret_ok {
+    write_response(&mut stream, "401 Unauthorized").await;
+    return Err("bad secret token".to_string());
+  }
+  let Some(len) = content_length.filter(|l| *l <= MAX_BODY_BYTES) else {
+    write_response(&mut stream, "413 Payload Too Large").await;
+    return Err("missing or too large content-length".to_string());
+  };
+
+  let mut buf = [0u8; 8192];
+  while body.len() < len {
+    let n = stream.read(&mut buf).await.map_err(|e| format!("read failed: {e}"))?;
+    if n == 0 {
+      return Err("connection closed before body was read".to_string());
+    }
+    body.extend_from_slice(&buf[..n]);
+  }
+  body.truncate(len);
+
+  let update: Value = match serde_json::from_slice(&body) {
+    Ok(v) => v,
+    Err(e) => {
+      write_response(&mut stream, "400 Bad Request").await;
+      return Err(format!("invalid json: {e}"));
+    }
+  };
+
+  // Acknowledge first so Telegram doesn't retry while Codex works on the message.
+  write_response(&mut stream, "200 OK").await;
+  let _ = tx.send(update);
+  Ok(())
+}
+
+async fn read_head(stream: &mut TcpStream) -> Result<(String, Vec<u8>), String> {
+  let mut data: Vec<u8> = Vec::with_capacity(2048);
+  let mut buf = [0u8; 2048];
+  loop {
+    if let Some(idx) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+      let head = String::from_utf8_lossy(&data[..idx]).to_string();
+      let rest = data[idx + 4..].to_vec();
+      return Ok((head, rest));
+    }
+    if data.len() > MAX_HEAD_BYTES {
+      return Err("request head too large".to_string());
+    }
+    let n = stream.read(&mut buf).await.map_err(|e| format!("read failed: {e}"))?;
+    if n == 0 {
+      return Err("connection closed".to_string());
+    }
+    data.extend_from_slice(&buf[..n]);
+  }
+}
+
+async fn write_response(stream: &mut TcpStream, status: &str) {
+  let raw = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+  let _ = stream.write_all(raw.as_bytes()).await;
+  let _ = stream.flush().await;
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  if a.len() != b.len() {
+    return false;
+  }
+  a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
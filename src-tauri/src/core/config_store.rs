@@ -15,26 +15,230 @@ impl Default for TokenStorageMode {
   }
 }
 
+// How the bot receives updates from Telegram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TelegramMode {
+  // getUpdates long polling (works behind NAT, no public URL needed).
+  Polling,
+  // Telegram calls `webhook_url`, which a reverse proxy forwards to `webhook_listen`.
+  Webhook,
+}
+
+impl Default for TelegramMode {
+  fn default() -> Self {
+    Self::Polling
+  }
+}
+
+// How a Codex answer is shown while it streams. Chats can override it with /stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamMode {
+  // A new message every few sentences.
+  Chunks,
+  // One message edited in place as text arrives.
+  Edit,
+  // Nothing until the turn completes, then the whole answer.
+  Final,
+}
+
+impl Default for StreamMode {
+  fn default() -> Self {
+    Self::Chunks
+  }
+}
+
+// What to do with messages that arrive while Codex is still answering in the same chat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BusyPolicy {
+  // Run queued messages one by one after the current turn.
+  Queue,
+  // Send everything queued as a single prompt after the current turn.
+  Merge,
+  // Ask the user to wait (messages are dropped).
+  Reject,
+}
+
+impl Default for BusyPolicy {
+  fn default() -> Self {
+    Self::Queue
+  }
+}
+
+// Codex sandbox for a chat's threads, from most to least restrictive (the order is used for bounds).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SandboxMode {
+  ReadOnly,
+  WorkspaceWrite,
+}
+
+impl Default for SandboxMode {
+  fn default() -> Self {
+    Self::ReadOnly
+  }
+}
+
+// When Codex asks before running a command. Anything but `never` lets the chat approve commands
+// outside the sandbox, so the owner picks which policies chats may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApprovalPolicy {
+  Never,
+  OnRequest,
+  Untrusted,
+}
+
+impl Default for ApprovalPolicy {
+  fn default() -> Self {
+    Self::Never
+  }
+}
+
+// Reasoning effort, lowest first (the order is used for bounds).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasoningEffort {
+  Minimal,
+  Low,
+  Medium,
+  High,
+}
+
+// What a Telegram user may do with the bot, lowest first (the order is used for checks).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+  // Can look (threads, queue, ping) but not talk to Codex.
+  ReadOnly,
+  User,
+  // Also workspaces, archiving, write sandbox and approvals.
+  Operator,
+  Owner,
+}
+
+// Grants `role` to a user (in any chat), to everyone in a chat, or to one user in one chat.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessRule {
+  #[serde(default)]
+  pub user_id: Option<i64>,
+  #[serde(default)]
+  pub chat_id: Option<i64>,
+  pub role: Role,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TelegramConfig {
+  // Legacy allowlist: everyone in these chats is an operator. Prefer `access`.
   #[serde(default)]
   pub allowed_chat_ids: Vec<i64>,
+  #[serde(default)]
+  pub access: Vec<AccessRule>,
   #[serde(default = "default_poll_timeout_sec")]
   pub poll_timeout_sec: u64,
   #[serde(default)]
   pub token_storage: TokenStorageMode,
+  #[serde(default)]
+  pub busy_policy: BusyPolicy,
+  #[serde(default)]
+  pub stream_mode: StreamMode,
+  // Max number of messages waiting per chat (queue / merge policies).
+  #[serde(default = "default_queue_max_depth")]
+  pub queue_max_depth: usize,
+  #[serde(default)]
+  pub mode: TelegramMode,
+  // Public HTTPS URL registered with setWebhook (webhook mode only).
+  #[serde(default)]
+  pub webhook_url: Option<String>,
+  // Local address of the webhook listener. Plain HTTP: terminate TLS in the reverse proxy.
+  #[serde(default = "default_webhook_listen")]
+  pub webhook_listen: String,
+  // Bot API server, e.g. a self-hosted `telegram-bot-api` at http://127.0.0.1:8081.
+  #[serde(default = "default_api_base_url")]
+  pub api_base_url: String,
+  // The server runs with `--local`: big files, and getFile returns absolute paths on its disk.
+  #[serde(default)]
+  pub local_bot_api: bool,
+  // Photos/documents above this size are refused instead of downloaded.
+  #[serde(default = "default_attachment_max_bytes")]
+  pub attachment_max_bytes: u64,
+  // MIME types accepted as attachments; "type/*" matches a whole family.
+  #[serde(default = "default_attachment_mime_allowlist")]
+  pub attachment_mime_allowlist: Vec<String>,
+  // Replying to an answer from another thread switches the chat to that thread first.
+  #[serde(default)]
+  pub reply_follows_thread: bool,
+  // Answers longer than this many chars, or needing more than `document_min_messages` messages,
+  // also go out as a Markdown file; in final mode the file replaces the messages (0 = off). Either
+  // threshold alone is enough, so the lower one wins; the message count is off by default (a
+  // series message holds up to 900 chars).
+  #[serde(default = "default_document_min_chars")]
+  pub document_min_chars: usize,
+  #[serde(default)]
+  pub document_min_messages: usize,
+  // With a file, fenced code blocks of at least this many chars are sent as files too (0 = off).
+  #[serde(default = "default_code_file_min_chars")]
+  pub code_file_min_chars: usize,
 }
 
 fn default_poll_timeout_sec() -> u64 {
   20
 }
 
+fn default_queue_max_depth() -> usize {
+  5
+}
+
+fn default_webhook_listen() -> String {
+  "127.0.0.1:8787".to_string()
+}
+
+pub fn default_api_base_url() -> String {
+  "https://api.telegram.org".to_string()
+}
+
+fn default_attachment_max_bytes() -> u64 {
+  20 * 1024 * 1024
+}
+
+fn default_document_min_chars() -> usize {
+  12_000
+}
+
+fn default_code_file_min_chars() -> usize {
+  1500
+}
+
+fn default_attachment_mime_allowlist() -> Vec<String> {
+  ["image/*", "text/*", "application/pdf", "application/json", "application/zip"]
+    .iter()
+    .map(|s| s.to_string())
+    .collect()
+}
+
 impl Default for TelegramConfig {
   fn default() -> Self {
     Self {
       allowed_chat_ids: vec![],
+      access: vec![],
       poll_timeout_sec: default_poll_timeout_sec(),
       token_storage: TokenStorageMode::default(),
+      busy_policy: BusyPolicy::default(),
+      stream_mode: StreamMode::default(),
+      queue_max_depth: default_queue_max_depth(),
+      mode: TelegramMode::default(),
+      webhook_url: None,
+      webhook_listen: default_webhook_listen(),
+      api_base_url: default_api_base_url(),
+      local_bot_api: false,
+      attachment_max_bytes: default_attachment_max_bytes(),
+      attachment_mime_allowlist: default_attachment_mime_allowlist(),
+      reply_follows_thread: false,
+      document_min_chars: default_document_min_chars(),
+      document_min_messages: 0,
+      code_file_min_chars: default_code_file_min_chars(),
     }
   }
 }
@@ -60,6 +264,61 @@ pub struct CodexConfig {
   // If false, universal_instructions always apply (as a "global" baseline).
   #[serde(default = "default_universal_fallback_only")]
   pub universal_fallback_only: bool,
+
+  // How long an approval request or a Codex question waits for an answer before it is
+  // declined (or answered with nothing) automatically.
+  #[serde(default = "default_approval_timeout_sec")]
+  pub approval_timeout_sec: u64,
+
+  // Named folders a Telegram chat can be bound to with /workspace. Unbound chats use workspace_dir.
+  #[serde(default)]
+  pub workspaces: Vec<WorkspaceConfig>,
+
+  // Upper bounds for what a chat may pick in /settings.
+  #[serde(default)]
+  pub chat_limits: ChatLimits,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatLimits {
+  // Models a chat may switch to. Empty = chats always use the Codex default model.
+  #[serde(default)]
+  pub models: Vec<String>,
+  #[serde(default = "default_max_effort")]
+  pub max_effort: ReasoningEffort,
+  #[serde(default)]
+  pub max_sandbox: SandboxMode,
+  // Never empty in practice: an empty list is treated as `[never]`.
+  #[serde(default = "default_approval_policies")]
+  pub approval_policies: Vec<ApprovalPolicy>,
+}
+
+impl Default for ChatLimits {
+  fn default() -> Self {
+    Self {
+      models: vec![],
+      max_effort: default_max_effort(),
+      max_sandbox: SandboxMode::default(),
+      approval_policies: default_approval_policies(),
+    }
+  }
+}
+
+fn default_max_effort() -> ReasoningEffort {
+  ReasoningEffort::High
+}
+
+fn default_approval_policies() -> Vec<ApprovalPolicy> {
+  vec![ApprovalPolicy::Never]
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceConfig {
+  pub name: String,
+  pub path: String,
+  // Extra instructions for threads in this workspace (always applied, unlike universal_instructions).
+  #[serde(default)]
+  pub instructions: String,
 }
 
 impl Default for CodexConfig {
@@ -69,6 +328,9 @@ impl Default for CodexConfig {
       workspace_dir: None,
       universal_instructions: String::new(),
       universal_fallback_only: default_universal_fallback_only(),
+      approval_timeout_sec: default_approval_timeout_sec(),
+      workspaces: vec![],
+      chat_limits: ChatLimits::default(),
     }
   }
 }
@@ -77,6 +339,50 @@ fn default_universal_fallback_only() -> bool {
   true
 }
 
+fn default_approval_timeout_sec() -> u64 {
+  300
+}
+
+// Local speech-to-text for voice messages. Contract: `command args...` is run with `{input}`
+// replaced by the audio file path (appended as the last argument if no arg has it) and `{model}`
+// by `model_path`; stdout is the transcript, a non-zero exit is an error. The input is the
+// original OGG/Opus (or audio) file, so wrap tools that need WAV in a script that converts first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SttConfig {
+  // Empty = voice messages are not transcribed.
+  #[serde(default)]
+  pub command: String,
+  #[serde(default)]
+  pub args: Vec<String>,
+  #[serde(default)]
+  pub model_path: Option<String>,
+  #[serde(default = "default_stt_timeout_sec")]
+  pub timeout_sec: u64,
+  // Longer voice messages are refused before download.
+  #[serde(default = "default_stt_max_duration_sec")]
+  pub max_duration_sec: u64,
+}
+
+impl Default for SttConfig {
+  fn default() -> Self {
+    Self {
+      command: String::new(),
+      args: vec![],
+      model_path: None,
+      timeout_sec: default_stt_timeout_sec(),
+      max_duration_sec: default_stt_max_duration_sec(),
+    }
+  }
+}
+
+fn default_stt_timeout_sec() -> u64 {
+  120
+}
+
+fn default_stt_max_duration_sec() -> u64 {
+  600
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UiConfig {
   // User-selected language code (e.g. "en", "uk").
@@ -99,6 +405,8 @@ pub struct AppConfig {
   pub codex: CodexConfig,
   #[serde(default)]
   pub ui: UiConfig,
+  #[serde(default)]
+  pub stt: SttConfig,
 }
 
 pub fn load_config(path: &PathBuf) -> Result<AppConfig, String> {
@@ -0,0 +1,54 @@
+// Code-fence state along a text, computed in one pass so callers weighing many cut points don't
+// rescan the prefix for each: which fence (by language tag, `Some("")` when untagged) is open at a
+// byte offset. Same fence rules as the Telegram markdown renderer. Used to split answers without
+// breaking code blocks.
+pub struct FenceMap {
+  lines: Vec<FenceLine>,
+}
+
+struct FenceLine {
+  start: usize,
+  // Open fence before the line, and past its "```" if the line is a fence.
+  before: Option<String>,
+  fence: Option<(usize, Option<String>)>,
+}
+
+impl FenceMap {
+  // `open`: the fence already open where `text` starts.
+  pub fn new(open: Option<&str>, text: &str) -> Self {
+    let mut state = open.map(str::to_string);
+    let mut lines = vec![];
+    let mut start = 0usize;
+    for line in text.split('\n') {
+      let indent = line.len() - line.trim_start().len();
+      let fence = line.trim_start().strip_prefix("```").map(|info| {
+        let after = match state {
+          Some(_) => None,
+          None => Some(info.split_whitespace().next().unwrap_or("").to_string()),
+        };
+        (start + indent + 3, after)
+      });
+      let before = state.clone();
+      if let Some((_, after)) = &fence {
+        state = after.clone();
+      }
+      lines.push(FenceLine { start, before, fence });
+      start += line.len() + 1;
+    }
+    Self { lines }
+  }
+
+  // The fence left open by `text[..at]`.
+  pub fn open_at(&self, at: usize) -> Option<&str> {
+    let idx = self.lines.partition_point(|l| l.start <= at).saturating_sub(1);
+    let line = self.lines.get(idx)?;
+    match &line.fence {
+      Some((end, after)) if at >= *end => after.as_deref(),
+      _ => line.before.as_deref(),
+    }
+  }
+
+  pub fn inside(&self, at: usize) -> bool {
+    self.open_at(at).is_some()
+  }
+}
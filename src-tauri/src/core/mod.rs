@@ -3,3 +3,5 @@ pub mod secrets;
 pub mod paths;
 pub mod logbus;
 pub mod time;
+pub mod stt;
+pub mod fences;
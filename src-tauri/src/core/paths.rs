@@ -21,10 +21,34 @@ pub fn telegram_token_fallback_path(app: &AppHandle) -> Result<PathBuf, String>
   Ok(app_data_dir(app)?.join("telegram-token.txt"))
 }
 
+pub fn telegram_access_requests_path(app: &AppHandle) -> Result<PathBuf, String> {
+  Ok(app_data_dir(app)?.join("telegram-access-requests.json"))
+}
+
+pub fn telegram_answer_links_path(app: &AppHandle) -> Result<PathBuf, String> {
+  Ok(app_data_dir(app)?.join("telegram-answer-links.json"))
+}
+
+pub fn telegram_chat_prefs_path(app: &AppHandle) -> Result<PathBuf, String> {
+  Ok(app_data_dir(app)?.join("telegram-chat-prefs.json"))
+}
+
+pub fn telegram_inbox_dir(app: &AppHandle) -> Result<PathBuf, String> {
+  Ok(app_data_dir(app)?.join("telegram-inbox"))
+}
+
 pub fn codex_chat_threads_path(app: &AppHandle) -> Result<PathBuf, String> {
   Ok(app_data_dir(app)?.join("codex-chat-threads.json"))
 }
 
+pub fn codex_chat_settings_path(app: &AppHandle) -> Result<PathBuf, String> {
+  Ok(app_data_dir(app)?.join("codex-chat-settings.json"))
+}
+
+pub fn codex_chat_workspaces_path(app: &AppHandle) -> Result<PathBuf, String> {
+  Ok(app_data_dir(app)?.join("codex-chat-workspaces.json"))
+}
+
 pub fn codex_home_dir(app: &AppHandle) -> Result<PathBuf, String> {
   Ok(app_data_dir(app)?.join("codex-home"))
 }
@@ -0,0 +1,47 @@
+use std::{path::Path, time::Duration};
+
+use tokio::process::Command;
+
+use crate::core::config_store::SttConfig;
+
+pub fn is_configured(cfg: &SttConfig) -> bool {
+  !cfg.command.trim().is_empty()
+}
+
+// Runs the configured speech-to-text command on `audio` and returns the trimmed stdout.
+pub async fn transcribe(cfg: &SttConfig, audio: &Path) -> Result<String, String> {
+  let program = cfg.command.trim();
+  if program.is_empty() {
+    return Err("speech-to-text command is not configured".to_string());
+  }
+  let input = audio.to_string_lossy().to_string();
+  let model = cfg.model_path.clone().unwrap_or_default();
+
+  let mut args: Vec<String> = cfg
+    .args
+    .iter()
+    .map(|a| a.replace("{input}", &input).replace("{model}", &model))
+    .collect();
+  if !cfg.args.iter().any(|a| a.contains("{input}")) {
+    args.push(input);
+  }
+
+  let mut cmd = Command::new(program);
+  cmd.args(&args).kill_on_drop(true);
+  let timeout = Duration::from_secs(cfg.timeout_sec.clamp(5, 1800));
+  let out = tokio::time::timeout(timeout, cmd.output())
+    .await
+    .map_err(|_| "speech-to-text timed out".to_string())?
+    .map_err(|e| format!("failed to run {program}: {e}"))?;
+
+  if !out.status.success() {
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let tail: String = stderr.trim().chars().rev().take(300).collect::<Vec<_>>().into_iter().rev().collect();
+    return Err(format!("speech-to-text exit {}: {tail}", out.status.code().unwrap_or(-1)));
+  }
+  let text = String::from_utf8_lossy(&out.stdout).trim().to_string();
+  if text.is_empty() {
+    return Err("speech-to-text returned an empty transcript".to_string());
+  }
+  Ok(text)
+}
@@ -1,7 +1,7 @@
 mod connectors;
 mod core;
 
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use tauri::{AppHandle, Manager, State};
 use tokio::sync::RwLock;
@@ -44,6 +44,9 @@ async fn save_config(app: AppHandle, state: State<'_, AppState>, cfg: config_sto
     .codex
     .set_universal_instructions(cfg.codex.universal_instructions.clone(), cfg.codex.universal_fallback_only)
     .await;
+  state.codex.set_approval_timeout(cfg.codex.approval_timeout_sec).await;
+  state.codex.set_workspaces(cfg.codex.workspaces.clone()).await;
+  state.codex.set_chat_limits(cfg.codex.chat_limits.clone()).await;
 
   let path = paths::config_path(&app)?;
   config_store::save_config(&path, &cfg)
@@ -98,6 +101,49 @@ async fn telegram_status(state: State<'_, AppState>) -> Result<connectors::teleg
   Ok(state.telegram.status().await)
 }
 
+// One-time code for `/pair` in a new chat (valid for a few minutes, single use).
+#[tauri::command]
+async fn telegram_pair_start(state: State<'_, AppState>) -> Result<connectors::telegram::types::TelegramPairingCode, String> {
+  state.telegram.start_pairing().await
+}
+
+#[tauri::command]
+async fn telegram_pair_cancel(state: State<'_, AppState>) -> Result<(), String> {
+  state.telegram.cancel_pairing().await;
+  Ok(())
+}
+
+#[tauri::command]
+async fn telegram_access_requests(
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<Vec<connectors::telegram::types::TelegramAccessRequest>, String> {
+  state.telegram.access_requests(&app).await
+}
+
+// `user_id` picks the requester (group chats hold one request per member). A private chat is added
+// to the allowlist; in a group only the requester gets the user role there. The chat gets a
+// confirmation when the bot is running.
+#[tauri::command]
+async fn telegram_access_request_approve(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  chat_id: i64,
+  user_id: Option<i64>,
+) -> Result<(), String> {
+  state.telegram.approve_access_request(&app, chat_id, user_id).await
+}
+
+#[tauri::command]
+async fn telegram_access_request_deny(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  chat_id: i64,
+  user_id: Option<i64>,
+) -> Result<(), String> {
+  state.telegram.deny_access_request(&app, chat_id, user_id).await
+}
+
 #[tauri::command]
 async fn telegram_self_test(app: AppHandle, state: State<'_, AppState>) -> Result<connectors::telegram::self_test::TelegramSelfTestResult, String> {
   let cfg = state.config.read().await.clone();
@@ -139,6 +185,9 @@ pub fn run() {
         cfg0.codex.universal_instructions.clone(),
         cfg0.codex.universal_fallback_only,
       ));
+      tauri::async_runtime::block_on(codex.set_approval_timeout(cfg0.codex.approval_timeout_sec));
+      tauri::async_runtime::block_on(codex.set_workspaces(cfg0.codex.workspaces.clone()));
+      tauri::async_runtime::block_on(codex.set_chat_limits(cfg0.codex.chat_limits.clone()));
       let telegram = TelegramRuntime::new(cfg.clone(), codex.clone(), logs.clone());
 
       // Warm up Codex on startup so the UI doesn't look "stuck" and the first Telegram message is faster.
@@ -167,17 +216,31 @@ pub fn run() {
       codex_doctor,
       codex_thread_list,
       codex_thread_read,
+      codex_thread_new,
+      codex_thread_rename,
+      codex_thread_archive,
+      codex_thread_fork,
+      codex_chat_settings,
+      codex_chat_settings_set,
       codex_install,
       codex_stop,
       codex_login_chatgpt,
       codex_logout,
+      codex_interrupt,
+      codex_pending_requests,
+      codex_answer_user_input,
       telegram_token_status,
       telegram_set_token,
       telegram_delete_token,
       telegram_start,
       telegram_stop,
       telegram_status,
-      telegram_self_test
+      telegram_self_test,
+      telegram_pair_start,
+      telegram_pair_cancel,
+      telegram_access_requests,
+      telegram_access_request_approve,
+      telegram_access_request_deny
     ])
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
@@ -204,8 +267,9 @@ async fn codex_thread_list(
   state: State<'_, AppState>,
   limit: Option<u32>,
   cursor: Option<String>,
+  archived: Option<bool>,
 ) -> Result<connectors::codex::types::CodexThreadListResponse, String> {
-  Ok(state.codex.list_threads(limit.unwrap_or(30), cursor).await?)
+  Ok(state.codex.list_threads(limit.unwrap_or(30), cursor, archived).await?)
 }
 
 #[tauri::command]
@@ -217,6 +281,62 @@ async fn codex_thread_read(
   Ok(state.codex.read_thread(thread_id, max_items.unwrap_or(120)).await?)
 }
 
+// `topic_id` picks a forum topic inside the chat (None = the chat itself); the same below.
+#[tauri::command]
+async fn codex_thread_new(state: State<'_, AppState>, chat_id: i64, topic_id: Option<i64>) -> Result<String, String> {
+  let chat = connectors::codex::types::ChatKey::new(chat_id, topic_id);
+  state.codex.start_new_thread_for_chat(chat).await
+}
+
+#[tauri::command]
+async fn codex_thread_rename(state: State<'_, AppState>, thread_id: String, name: String) -> Result<(), String> {
+  state.codex.set_thread_name(&thread_id, &name).await
+}
+
+#[tauri::command]
+async fn codex_thread_archive(state: State<'_, AppState>, thread_id: String, archived: bool) -> Result<(), String> {
+  state.codex.set_thread_archived(&thread_id, archived).await
+}
+
+// Forks the thread; with `chat_id` the chat is switched to the fork.
+#[tauri::command]
+async fn codex_thread_fork(
+  state: State<'_, AppState>,
+  thread_id: String,
+  chat_id: Option<i64>,
+  topic_id: Option<i64>,
+) -> Result<String, String> {
+  let chat = chat_id.map(|c| connectors::codex::types::ChatKey::new(c, topic_id));
+  let new_id = state.codex.fork_thread(&thread_id, chat).await?;
+  if let Some(chat) = chat {
+    state.codex.attach_chat_to_thread(chat, new_id.clone()).await?;
+  }
+  Ok(new_id)
+}
+
+// The chat's own choices (unset = default), as shown in /settings.
+#[tauri::command]
+async fn codex_chat_settings(
+  state: State<'_, AppState>,
+  chat_id: i64,
+  topic_id: Option<i64>,
+) -> Result<connectors::codex::types::CodexChatSettings, String> {
+  let chat = connectors::codex::types::ChatKey::new(chat_id, topic_id);
+  Ok(state.codex.chat_settings(chat).await)
+}
+
+// Refused when a value is above `codex.chat_limits`; returns the effective settings.
+#[tauri::command]
+async fn codex_chat_settings_set(
+  state: State<'_, AppState>,
+  chat_id: i64,
+  topic_id: Option<i64>,
+  settings: connectors::codex::types::CodexChatSettings,
+) -> Result<connectors::codex::types::CodexChatSettings, String> {
+  let chat = connectors::codex::types::ChatKey::new(chat_id, topic_id);
+  state.codex.set_chat_settings(chat, settings).await
+}
+
 #[tauri::command]
 async fn codex_install(state: State<'_, AppState>) -> Result<connectors::codex::types::CodexDoctor, String> {
   state.codex.install_local_codex().await
@@ -240,3 +360,28 @@ async fn codex_login_chatgpt(state: State<'_, AppState>) -> Result<connectors::c
 async fn codex_logout(state: State<'_, AppState>) -> Result<(), String> {
   state.codex.logout().await
 }
+
+#[tauri::command]
+async fn codex_interrupt(state: State<'_, AppState>, chat_id: i64, topic_id: Option<i64>) -> Result<bool, String> {
+  let chat = connectors::codex::types::ChatKey::new(chat_id, topic_id);
+  state.codex.interrupt_turn(chat).await
+}
+
+#[tauri::command]
+async fn codex_pending_requests(
+  state: State<'_, AppState>,
+) -> Result<Vec<connectors::codex::types::CodexServerRequest>, String> {
+  Ok(state.codex.pending_server_requests().await)
+}
+
+#[tauri::command]
+async fn codex_answer_user_input(
+  state: State<'_, AppState>,
+  chat_id: i64,
+  topic_id: Option<i64>,
+  request_id: u64,
+  answers: HashMap<String, Vec<String>>,
+) -> Result<(), String> {
+  let chat = connectors::codex::types::ChatKey::new(chat_id, topic_id);
+  state.codex.answer_user_input(chat, request_id, answers).await
+}
@@ -1,4 +1,5 @@
 import React from 'react';
+import { listen } from '@tauri-apps/api/event';
 import { DashboardHeader } from './components/DashboardHeader';
 import { Sidebar } from './components/Sidebar';
 import { useTheme } from './hooks/useTheme';
@@ -53,6 +54,25 @@ export function Dashboard() {
     return () => window.clearInterval(id);
   }, [refresh]);
 
+  // The backend adds chats to the allowlist on its own (/pair, approved access requests); reload the
+  // config right away so a save from the UI doesn't write back a list without them.
+  React.useEffect(() => {
+    let unlisten: null | (() => void) = null;
+    (async () => {
+      try {
+        const u = await listen<{ chatId?: number }>('telegram://paired', () => {
+          refresh();
+        });
+        unlisten = () => u();
+      } catch {
+        // ignore (non-Tauri / no event system)
+      }
+    })();
+    return () => {
+      if (unlisten) unlisten();
+    };
+  }, [refresh]);
+
   return (
     <div className="flex h-screen app-ambient">
       <Sidebar
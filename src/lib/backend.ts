@@ -11,10 +11,38 @@ async function getInvoke(): Promise<InvokeFn> {
   return mod.invoke as InvokeFn;
 }
 
+export type Role = 'read_only' | 'user' | 'operator' | 'owner';
+
+// Grants `role` to a user (any chat), to everyone in a chat, or to one user in one chat.
+export type AccessRule = {
+  user_id?: number | null;
+  chat_id?: number | null;
+  role: Role;
+};
+
 export type TelegramConfig = {
+  // Legacy allowlist: everyone in these chats is an operator. Prefer `access`.
   allowed_chat_ids: number[];
+  access?: AccessRule[];
   poll_timeout_sec: number;
   token_storage: 'keychain' | 'file';
+  // What to do with messages sent while Codex is still answering in the same chat.
+  busy_policy?: 'queue' | 'merge' | 'reject';
+  stream_mode?: 'chunks' | 'edit' | 'final';
+  queue_max_depth?: number;
+  mode?: 'polling' | 'webhook';
+  webhook_url?: string | null;
+  webhook_listen?: string;
+  api_base_url?: string;
+  local_bot_api?: boolean;
+  attachment_max_bytes?: number;
+  attachment_mime_allowlist?: string[];
+  // Replying to an answer from another thread switches the chat to that thread first.
+  reply_follows_thread?: boolean;
+  // Long answers also go out as a Markdown file (0 = off); big code blocks as separate files.
+  document_min_chars?: number;
+  document_min_messages?: number;
+  code_file_min_chars?: number;
 };
 
 export type CodexConfig = {
@@ -23,6 +51,30 @@ export type CodexConfig = {
   workspace_dir: string | null;
   universal_instructions?: string;
   universal_fallback_only?: boolean;
+  // Seconds an approval request waits for an answer in Telegram before it is declined.
+  approval_timeout_sec?: number;
+  // Named folders a Telegram chat can be bound to with /workspace.
+  workspaces?: WorkspaceConfig[];
+  // Upper bounds for what a chat may pick in /settings.
+  chat_limits?: ChatLimits;
+};
+
+export type SandboxMode = 'read-only' | 'workspace-write';
+export type ApprovalPolicy = 'never' | 'on-request' | 'untrusted';
+export type ReasoningEffort = 'minimal' | 'low' | 'medium' | 'high';
+
+export type ChatLimits = {
+  // Empty = chats always use the Codex default model.
+  models: string[];
+  max_effort: ReasoningEffort;
+  max_sandbox: SandboxMode;
+  approval_policies: ApprovalPolicy[];
+};
+
+export type WorkspaceConfig = {
+  name: string;
+  path: string;
+  instructions?: string;
 };
 
 export type UiConfig = {
@@ -30,10 +82,20 @@ export type UiConfig = {
   language: string | null;
 };
 
+// Voice transcription: `command args...`, `{input}` = audio file, `{model}` = model_path; stdout = transcript.
+export type SttConfig = {
+  command: string;
+  args: string[];
+  model_path?: string | null;
+  timeout_sec?: number;
+  max_duration_sec?: number;
+};
+
 export type AppConfig = {
   telegram: TelegramConfig;
   codex: CodexConfig;
   ui?: UiConfig;
+  stt?: SttConfig;
 };
 
 export type TelegramStatus = {
@@ -52,10 +114,39 @@ export type LogEntry = {
   msg: string;
 };
 
+// Someone without access who wrote to the bot; one entry per chat and sender (`chat_id` + `user_id`).
+export type TelegramAccessRequest = {
+  chat_id: number;
+  chat_title?: string | null;
+  user_id?: number | null;
+  name: string;
+  username?: string | null;
+  first_seen_unix_ms: number;
+  last_seen_unix_ms: number;
+  message_count: number;
+  note?: string | null;
+  status: 'pending' | 'denied';
+};
+
+// One-time code the user sends to the bot as `/pair CODE`.
+export type TelegramPairingCode = {
+  code: string;
+  expires_at_unix_ms: number;
+};
+
+export type TelegramWebhookInfo = {
+  url: string;
+  pending_update_count: number;
+  last_error_date?: number | null;
+  last_error_message?: string | null;
+  ip_address?: string | null;
+};
+
 export type TelegramSelfTestResult = {
   ok: boolean;
   bot_username: string | null;
   sent_test_message: boolean;
+  webhook_info?: TelegramWebhookInfo | null;
   error: string | null;
 };
 
@@ -115,6 +206,49 @@ export type CodexThreadReadResponse = {
   items: CodexThreadReadItem[];
 };
 
+export type CodexApprovalRequest = {
+  type: 'approval';
+  requestId: number;
+  kind: 'commandExecution' | 'fileChange';
+  chatId: number;
+  topicId?: number | null;
+  threadId: string;
+  turnId: string;
+  command?: string | null;
+  cwd?: string | null;
+  reason?: string | null;
+  changes?: string | null;
+};
+
+export type CodexUserInputQuestion = {
+  id: string;
+  header?: string | null;
+  question: string;
+  options: { label: string; description?: string | null }[];
+  isOther: boolean;
+  isSecret: boolean;
+};
+
+export type CodexUserInputRequest = {
+  type: 'userInput';
+  requestId: number;
+  chatId: number;
+  topicId?: number | null;
+  threadId: string;
+  turnId: string;
+  questions: CodexUserInputQuestion[];
+};
+
+export type CodexServerRequest = CodexApprovalRequest | CodexUserInputRequest;
+
+// Per-chat settings; null = default.
+export type CodexChatSettings = {
+  model: string | null;
+  effort: ReasoningEffort | null;
+  sandbox: SandboxMode | null;
+  approvalPolicy: ApprovalPolicy | null;
+};
+
 export const backend = {
   async ping(): Promise<string> {
     const invoke = await getInvoke();
@@ -176,6 +310,31 @@ export const backend = {
     return invoke<TelegramStatus>('telegram_status');
   },
 
+  async telegramPairStart(): Promise<TelegramPairingCode> {
+    const invoke = await getInvoke();
+    return invoke<TelegramPairingCode>('telegram_pair_start');
+  },
+
+  async telegramPairCancel(): Promise<void> {
+    const invoke = await getInvoke();
+    await invoke<void>('telegram_pair_cancel');
+  },
+
+  async telegramAccessRequests(): Promise<TelegramAccessRequest[]> {
+    const invoke = await getInvoke();
+    return invoke<TelegramAccessRequest[]>('telegram_access_requests');
+  },
+
+  async telegramAccessRequestApprove(chatId: number, userId: number | null = null): Promise<void> {
+    const invoke = await getInvoke();
+    await invoke<void>('telegram_access_request_approve', { chatId, userId });
+  },
+
+  async telegramAccessRequestDeny(chatId: number, userId: number | null = null): Promise<void> {
+    const invoke = await getInvoke();
+    await invoke<void>('telegram_access_request_deny', { chatId, userId });
+  },
+
   async telegramSelfTest(): Promise<TelegramSelfTestResult> {
     const invoke = await getInvoke();
     return invoke<TelegramSelfTestResult>('telegram_self_test');
@@ -196,9 +355,51 @@ export const backend = {
     return invoke<CodexDoctor>('codex_doctor');
   },
 
-  async codexThreadList(limit = 30, cursor: string | null = null): Promise<CodexThreadListResponse> {
+  // archived: null = all threads, true/false = only archived / only active.
+  async codexThreadList(
+    limit = 30,
+    cursor: string | null = null,
+    archived: boolean | null = null,
+  ): Promise<CodexThreadListResponse> {
+    const invoke = await getInvoke();
+    return invoke<CodexThreadListResponse>('codex_thread_list', { limit, cursor, archived });
+  },
+
+  // Starts a fresh thread for one Telegram chat (other chats keep theirs).
+  // `topicId` picks a forum topic inside the chat (null = the chat itself); the same below.
+  async codexThreadNew(chatId: number, topicId: number | null = null): Promise<string> {
+    const invoke = await getInvoke();
+    return invoke<string>('codex_thread_new', { chatId, topicId });
+  },
+
+  async codexThreadRename(threadId: string, name: string): Promise<void> {
+    const invoke = await getInvoke();
+    await invoke<void>('codex_thread_rename', { threadId, name });
+  },
+
+  async codexThreadArchive(threadId: string, archived: boolean): Promise<void> {
+    const invoke = await getInvoke();
+    await invoke<void>('codex_thread_archive', { threadId, archived });
+  },
+
+  // Returns the new thread id; with chatId that chat switches to the fork.
+  async codexThreadFork(threadId: string, chatId: number | null = null, topicId: number | null = null): Promise<string> {
+    const invoke = await getInvoke();
+    return invoke<string>('codex_thread_fork', { threadId, chatId, topicId });
+  },
+
+  async codexChatSettings(chatId: number, topicId: number | null = null): Promise<CodexChatSettings> {
     const invoke = await getInvoke();
-    return invoke<CodexThreadListResponse>('codex_thread_list', { limit, cursor });
+    return invoke<CodexChatSettings>('codex_chat_settings', { chatId, topicId });
+  },
+
+  async codexChatSettingsSet(
+    chatId: number,
+    settings: CodexChatSettings,
+    topicId: number | null = null,
+  ): Promise<CodexChatSettings> {
+    const invoke = await getInvoke();
+    return invoke<CodexChatSettings>('codex_chat_settings_set', { chatId, topicId, settings });
   },
 
   async codexThreadRead(threadId: string, maxItems = 120): Promise<CodexThreadReadResponse> {
@@ -225,4 +426,24 @@ export const backend = {
     const invoke = await getInvoke();
     await invoke<void>('codex_logout');
   },
+
+  async codexInterrupt(chatId: number, topicId: number | null = null): Promise<boolean> {
+    const invoke = await getInvoke();
+    return invoke<boolean>('codex_interrupt', { chatId, topicId });
+  },
+
+  async codexPendingRequests(): Promise<CodexServerRequest[]> {
+    const invoke = await getInvoke();
+    return invoke<CodexServerRequest[]>('codex_pending_requests');
+  },
+
+  async codexAnswerUserInput(
+    chatId: number,
+    topicId: number | null,
+    requestId: number,
+    answers: Record<string, string[]>,
+  ): Promise<void> {
+    const invoke = await getInvoke();
+    await invoke<void>('codex_answer_user_input', { chatId, topicId, requestId, answers });
+  },
 };